@@ -20,7 +20,7 @@ fn quads_and_spheres(c: &mut Criterion) {
         let _ = cam.render_no_preview(&world);
     }));
     c.bench_function("Quads with BVH", |b| b.iter(||{
-        let world = BvhNode::new(&mut world);
+        let world = BvhNode::from_vec(world.objects.clone());
         let _ = cam.render_no_preview(&world);
     }));
 
@@ -30,7 +30,7 @@ fn quads_and_spheres(c: &mut Criterion) {
         let _ = cam.render_no_preview(&world);
     }));
     c.bench_function("Spheres with BVH", |b| b.iter(||{
-        let world = BvhNode::new(&mut world);
+        let world = BvhNode::from_vec(world.objects.clone());
         let _ = cam.render_no_preview(&world);
     }));
 
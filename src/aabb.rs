@@ -1,5 +1,6 @@
-use crate::{interval::Interval, vec3::Point3};
+use crate::{interval::Interval, vec3::{Point3, Vec3}};
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Default, Clone, Copy)]
 pub struct AABB {
     pub x: Interval,
@@ -43,6 +44,10 @@ impl AABB {
         }
     }
 
+    pub fn translate(self, offset: Vec3) -> Self {
+        AABB::new(self.x + offset.x, self.y + offset.y, self.z + offset.z)
+    }
+
     pub fn pad(self) -> Self {
         let delta = 0.0001;
         let new_x = if self.x.size() >= delta {self.x} else {self.x.expand(delta)};
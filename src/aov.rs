@@ -0,0 +1,99 @@
+use std::path::Path;
+use anyhow::Result;
+
+use crate::color::Color;
+use crate::framebuffer::Framebuffer;
+use crate::hittable::HitRecord;
+use crate::material::Material;
+use crate::ray::Ray;
+
+/// Layer names in the order of [`Aovs::layers`]
+pub const AOV_NAMES: [&str; 6] = ["albedo", "normal", "depth", "position", "uv", "object_id"];
+
+/// First-hit values of one camera ray, one color per layer
+pub(crate) type AovPixel = [Color; 6];
+
+/// Auxiliary images of what the camera rays hit first, see
+/// [`Camera::render_aovs`](crate::Camera::render_aovs). Pixels where
+/// the rays miss the scene are black in every layer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Aovs {
+    /// Base color of the material
+    pub albedo: Framebuffer,
+    /// Outward facing world space normal
+    pub normal: Framebuffer,
+    /// Distance from the camera, in all three channels
+    pub depth: Framebuffer,
+    /// World space hit position
+    pub position: Framebuffer,
+    /// Texture coordinates in the red and green channel
+    pub uv: Framebuffer,
+    /// Index of the primitive or mesh in the scene plus one, in all three
+    /// channels. Taken from the first sample, not averaged.
+    pub object_id: Framebuffer,
+}
+
+impl Aovs {
+    pub fn new(width: u32, height: u32) -> Self {
+        let layer = Framebuffer::new(width, height);
+        Aovs {
+            albedo: layer.clone(),
+            normal: layer.clone(),
+            depth: layer.clone(),
+            position: layer.clone(),
+            uv: layer.clone(),
+            object_id: layer,
+        }
+    }
+
+    pub fn layers(&self) -> [(&'static str, &Framebuffer); 6] {
+        let [albedo, normal, depth, position, uv, object_id] = AOV_NAMES;
+        [
+            (albedo, &self.albedo),
+            (normal, &self.normal),
+            (depth, &self.depth),
+            (position, &self.position),
+            (uv, &self.uv),
+            (object_id, &self.object_id),
+        ]
+    }
+
+    /// Saves every layer as an OpenEXR file next to `image_path`, named
+    /// after the image with the layer name appended, e.g. `image_normal.exr`.
+    pub fn save<P: AsRef<Path>>(&self, image_path: P) -> Result<()> {
+        let image_path = image_path.as_ref();
+        let stem = image_path.file_stem().unwrap_or_default().to_string_lossy();
+        for (name, layer) in self.layers() {
+            layer.save(image_path.with_file_name(format!("{}_{}.exr", stem, name)))?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn set(&mut self, i: u32, j: u32, pixel: &AovPixel) {
+        let [albedo, normal, depth, position, uv, object_id] = pixel;
+        self.albedo.set(i, j, *albedo);
+        self.normal.set(i, j, *normal);
+        self.depth.set(i, j, *depth);
+        self.position.set(i, j, *position);
+        self.uv.set(i, j, *uv);
+        self.object_id.set(i, j, *object_id);
+    }
+}
+
+/// Layer values for a camera ray `r` hitting `rec`
+pub(crate) fn aov_pixel(r: &Ray, rec: &HitRecord) -> AovPixel {
+    let albedo = match rec.material {
+        Some(ref material) => material.albedo(rec),
+        None => Material::default().albedo(rec),
+    };
+    let normal = if rec.front_face { rec.normal } else { -rec.normal };
+    let depth = rec.t * r.direction().norm();
+    [
+        albedo,
+        normal,
+        Color::repeat(depth),
+        rec.p,
+        Color::new(rec.uv.0, rec.uv.1, 0.),
+        Color::repeat(rec.object_id as f32),
+    ]
+}
@@ -1,23 +1,436 @@
-use raytracing::camera::Camera;
-use raytracing::hittable_list::HittableList;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use raytracing::camera::{Camera, AdaptiveSampling, PhysicalCamera, Projection, Stereo, StereoLayout};
+use raytracing::camera::{Integrator, PathTracer, Bidirectional, Whitted, Normals, Uv, AmbientOcclusion, TraversalCost};
+use raytracing::hittable_list::{HittableList, SCENES};
 use raytracing::bvh::BvhNode;
-use anyhow::Result;
+use raytracing::sampler::SamplerKind;
+use raytracing::photon::PhotonMapping;
+use raytracing::medium::{Atmosphere, ConstantMedium, GridMedium};
+use raytracing::voxel::VoxelGrid;
+use raytracing::color::{Color, DisplayTransform, ToneMapping};
+use raytracing::denoise::Denoiser;
+use raytracing::Point3;
+use anyhow::{Result, anyhow};
+use clap::{Parser, builder::PossibleValuesParser};
+
+/// Render one of the built-in scenes or an .obj file
+#[derive(Parser)]
+#[command(version, about)]
+struct Args {
+    /// Built-in scene to render
+    #[arg(short, long, default_value = "bugatti",
+          value_parser = PossibleValuesParser::new(SCENES))]
+    scene: String,
+
+    /// Render a Wavefront .obj file instead of a built-in scene
+    #[arg(short, long, conflicts_with = "scene")]
+    file: Option<String>,
+
+    /// Image width in pixels
+    #[arg(short, long, default_value_t = 800)]
+    width: u32,
+
+    /// Aspect ratio, either as a number or as "width:height"
+    #[arg(short, long, default_value = "16:9", value_parser = parse_aspect_ratio)]
+    aspect_ratio: f32,
+
+    /// Samples per pixel
+    #[arg(long, default_value_t = 10)]
+    spp: u32,
+
+    /// Maximum number of ray bounces
+    #[arg(long, default_value_t = 5)]
+    max_bounces: u32,
+
+    /// Light transport algorithm: path (tracing), bidirectional, photon
+    /// (mapping, always progressive) or whitted (direct light only), or a
+    /// debug view: normals, uv, ao[:radius] or bvh-cost[:max tests]
+    #[arg(long, default_value = "path")]
+    integrator: String,
+
+    /// Photons shot per pass with --integrator photon
+    #[arg(long)]
+    photons: Option<u32>,
+
+    /// Initial photon gather radius with --integrator photon, defaults to
+    /// a two hundredth of the scene size
+    #[arg(long)]
+    photon_radius: Option<f32>,
+
+    /// Fill the scene with haze of this density, the chance per scene unit
+    /// that light scatters
+    #[arg(long)]
+    fog: Option<f32>,
+
+    /// Fraction of the light scattered by --fog that is not absorbed
+    #[arg(long, requires = "fog")]
+    fog_albedo: Option<f32>,
+
+    /// Add smoke of the same density everywhere in a box, given by two
+    /// opposite corners as x0,y0,z0,x1,y1,z1
+    #[arg(long, value_parser = parse_box)]
+    smoke: Option<(Point3, Point3)>,
+
+    /// Density of --smoke, the chance per scene unit that light scatters
+    #[arg(long, requires = "smoke")]
+    smoke_density: Option<f32>,
+
+    /// Fraction of the light scattered by --smoke that is not absorbed
+    #[arg(long, requires = "smoke")]
+    smoke_albedo: Option<f32>,
+
+    /// Add smoke from a voxel grid of densities, a Mitsuba .vol file or raw
+    /// little-endian 32 bit floats of a cubic grid filling the unit cube
+    #[arg(long)]
+    volume: Option<String>,
+
+    /// Density of --volume where the grid's value is one, the chance per
+    /// scene unit that light scatters
+    #[arg(long, requires = "volume")]
+    volume_density: Option<f32>,
+
+    /// Fraction of the light scattered by --volume that is not absorbed,
+    /// defaults to 1 for smoke and 0.5 for fire
+    #[arg(long, requires = "volume")]
+    volume_albedo: Option<f32>,
+
+    /// Make --volume glow like fire, from a voxel grid of temperatures in
+    /// kelvin in the same formats
+    #[arg(long, requires = "volume")]
+    temperature: Option<String>,
+
+    /// Scale of the light given off with --temperature
+    #[arg(long, requires = "temperature")]
+    fire_brightness: Option<f32>,
+
+    /// Bounces before Russian roulette starts ending dim paths
+    #[arg(long, default_value_t = 3)]
+    roulette_bounces: u32,
+
+    /// Vertical field of view in degrees, overrides the scene's choice
+    #[arg(long)]
+    fov: Option<f32>,
+
+    /// Seed for all random sampling, same seed gives the same image
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+
+    /// Sample distribution: independent, stratified, halton, sobol or blue-noise
+    #[arg(long, default_value = "independent", value_parser = parse_sampler)]
+    sampler: SamplerKind,
+
+    /// Camera projection: perspective, orthographic[:height], fisheye[:fov] or equirectangular
+    #[arg(long, default_value = "perspective")]
+    projection: String,
+
+    /// Render both eyes into one image: side-by-side or over-under
+    #[arg(long, value_parser = parse_stereo_layout)]
+    stereo: Option<StereoLayout>,
+
+    /// Distance between the eyes in scene units with --stereo
+    #[arg(long, requires = "stereo")]
+    eye_separation: Option<f32>,
+
+    /// Distance at which both eyes see the same image with --stereo,
+    /// defaults to the distance to the scene's look-at point
+    #[arg(long, requires = "stereo")]
+    convergence: Option<f32>,
+
+    /// Lens aperture as the angle in degrees of the cone of rays through a pixel
+    #[arg(long)]
+    defocus_angle: Option<f32>,
+
+    /// Distance to the plane in focus, defaults to the distance to the scene's look-at point
+    #[arg(long)]
+    focus_dist: Option<f32>,
+
+    /// Scene time at which the shutter opens, for motion blur
+    #[arg(long)]
+    shutter_open: Option<f32>,
+
+    /// Scene time at which the shutter closes, for motion blur
+    #[arg(long)]
+    shutter_close: Option<f32>,
+
+    /// Expose like a real camera, scene colors are luminance in cd/m²
+    #[arg(long)]
+    physical: bool,
+
+    /// Aperture f-number with --physical, sets the depth of field
+    #[arg(long, requires = "physical")]
+    f_number: Option<f32>,
+
+    /// Shutter time in seconds with --physical, sets the motion blur
+    #[arg(long, requires = "physical")]
+    shutter_time: Option<f32>,
+
+    /// Sensor sensitivity with --physical
+    #[arg(long, requires = "physical")]
+    iso: Option<f32>,
+
+    /// Exposure compensation in stops with --physical
+    #[arg(long, requires = "physical", allow_hyphen_values = true)]
+    ev: Option<f32>,
+
+    /// Scene units per meter with --physical
+    #[arg(long, requires = "physical")]
+    units_per_meter: Option<f32>,
+
+    /// Filter the noise out of the image and the progressive preview
+    #[arg(long)]
+    denoise: bool,
+
+    /// Tone mapping for the preview and 8 bit images: clamp, reinhard, hable or aces
+    #[arg(long, default_value = "clamp", value_parser = parse_tone_mapping)]
+    tone_map: ToneMapping,
+
+    /// Display exposure in stops, applied before tone mapping
+    #[arg(long, default_value_t = 0., allow_hyphen_values = true)]
+    exposure: f32,
+
+    /// Where to save the rendered image, .exr, .hdr and .pfm keep the full dynamic range
+    #[arg(short, long, default_value = "image.png")]
+    output: PathBuf,
+
+    /// Choose the number of samples per pixel from its noise instead of using --spp
+    #[arg(long)]
+    adaptive: bool,
+
+    /// Fewest samples per pixel with --adaptive
+    #[arg(long, requires = "adaptive")]
+    min_spp: Option<u32>,
+
+    /// Most samples per pixel with --adaptive
+    #[arg(long, requires = "adaptive")]
+    max_spp: Option<u32>,
+
+    /// Relative noise level at which --adaptive stops sampling a pixel
+    #[arg(long, requires = "adaptive")]
+    max_error: Option<f32>,
+
+    /// Also save first-hit albedo, normal, depth, position, uv and object id
+    /// images as OpenEXR files next to the output
+    #[arg(long)]
+    aovs: bool,
+
+    /// Also save an image showing how many samples each pixel got
+    #[arg(long)]
+    sample_map: Option<PathBuf>,
+
+    /// Refine the whole image pass by pass, one sample per pixel at a time
+    #[arg(long)]
+    progressive: bool,
+
+    /// Stop progressive rendering after this many seconds
+    #[arg(long, requires = "progressive")]
+    time_budget: Option<f32>,
+
+    /// Render without opening a preview window
+    #[cfg(feature = "preview")]
+    #[arg(long)]
+    headless: bool,
+}
+
+fn parse_aspect_ratio(s: &str) -> Result<f32> {
+    let ratio = match s.split_once(':') {
+        Some((w, h)) => w.trim().parse::<f32>()? / h.trim().parse::<f32>()?,
+        None => s.parse()?,
+    };
+    if !ratio.is_finite() || ratio <= 0. {
+        return Err(anyhow!("aspect ratio must be positive"));
+    }
+    Ok(ratio)
+}
+
+fn parse_box(s: &str) -> Result<(Point3, Point3)> {
+    let coordinates = s.split(',').map(|c| c.trim().parse::<f32>()).collect::<Result<Vec<_>, _>>()?;
+    match coordinates[..] {
+        [x0, y0, z0, x1, y1, z1] => Ok((Point3::new(x0, y0, z0), Point3::new(x1, y1, z1))),
+        _ => Err(anyhow!("a box needs six coordinates")),
+    }
+}
+
+fn parse_sampler(s: &str) -> Result<SamplerKind> {
+    Ok(match s {
+        "independent" => SamplerKind::Independent,
+        "stratified" => SamplerKind::Stratified,
+        "halton" => SamplerKind::Halton,
+        "sobol" => SamplerKind::Sobol,
+        "blue-noise" => SamplerKind::BlueNoise,
+        _ => return Err(anyhow!("unknown sampler")),
+    })
+}
+
+/// Ambient occlusion radius defaults to a tenth of the distance to the
+/// look-at point.
+fn parse_integrator(s: &str, cam: &Camera, photons: PhotonMapping) -> Result<Box<dyn Integrator>> {
+    let (name, param) = match s.split_once(':') {
+        Some((name, param)) => (name, Some(param.trim().parse::<f32>()?)),
+        None => (s, None),
+    };
+    Ok(match (name, param) {
+        ("path", None) => Box::new(PathTracer),
+        ("bidirectional", None) => Box::new(Bidirectional),
+        ("photon", None) => Box::new(photons),
+        ("whitted", None) => Box::new(Whitted),
+        ("normals", None) => Box::new(Normals),
+        ("uv", None) => Box::new(Uv),
+        ("ao", radius) => {
+            let default = (cam.lookfrom - cam.lookat).norm() / 10.;
+            Box::new(AmbientOcclusion { radius: radius.unwrap_or(default) })
+        },
+        ("bvh-cost", max) => Box::new(TraversalCost { max: max.unwrap_or(TraversalCost::default().max) }),
+        _ => return Err(anyhow!("unknown integrator {}", s)),
+    })
+}
+
+fn parse_tone_mapping(s: &str) -> Result<ToneMapping> {
+    Ok(match s {
+        "clamp" => ToneMapping::Clamp,
+        "reinhard" => ToneMapping::Reinhard,
+        "hable" => ToneMapping::Hable,
+        "aces" => ToneMapping::Aces,
+        _ => return Err(anyhow!("unknown tone mapping")),
+    })
+}
+
+fn parse_stereo_layout(s: &str) -> Result<StereoLayout> {
+    Ok(match s {
+        "side-by-side" => StereoLayout::SideBySide,
+        "over-under" => StereoLayout::OverUnder,
+        _ => return Err(anyhow!("unknown stereo layout")),
+    })
+}
+
+/// Orthographic height defaults to what the perspective camera sees at the
+/// look-at point, fisheye field of view to 180 degrees.
+fn parse_projection(s: &str, cam: &Camera) -> Result<Projection> {
+    let (name, param) = match s.split_once(':') {
+        Some((name, param)) => (name, Some(param.trim().parse::<f32>()?)),
+        None => (s, None),
+    };
+    Ok(match (name, param) {
+        ("perspective", None) => Projection::Perspective,
+        ("orthographic", height) => {
+            let distance = (cam.lookfrom - cam.lookat).norm();
+            let default = 2. * (cam.fov.to_radians() / 2.).tan() * distance;
+            Projection::Orthographic { height: height.unwrap_or(default) }
+        },
+        ("fisheye", fov) => Projection::Fisheye { fov: fov.unwrap_or(180.) },
+        ("equirectangular", None) => Projection::Equirectangular,
+        _ => return Err(anyhow!("unknown projection {}", s)),
+    })
+}
 
 fn main() -> Result<()> {
+    let args = Args::parse();
+
     // Camera
-    let aspect_ratio = 16.0/9.0;
-    let image_width = 800;
-    let mut cam: Camera = Camera::new(aspect_ratio, image_width);
-    cam.samples_per_pixel = 10;
-    cam.max_bounces = 5;
+    let mut cam: Camera = Camera::new(args.aspect_ratio, args.width);
+    cam.samples_per_pixel = args.spp;
+    cam.max_bounces = args.max_bounces;
+    cam.roulette_bounces = args.roulette_bounces;
+    cam.time_budget = args.time_budget.map(Duration::from_secs_f32);
+    cam.seed = args.seed;
+    cam.sampler = args.sampler;
+    cam.denoiser = args.denoise.then(Denoiser::default);
+    cam.display = DisplayTransform { exposure: args.exposure, tone_mapping: args.tone_map };
+    if args.adaptive {
+        let default = AdaptiveSampling::default();
+        cam.adaptive = Some(AdaptiveSampling {
+            min_samples: args.min_spp.unwrap_or(default.min_samples),
+            max_samples: args.max_spp.unwrap_or(default.max_samples),
+            max_error: args.max_error.unwrap_or(default.max_error),
+        });
+    }
 
     // World
     let mut world = HittableList::new();
-    world.bugatti(&mut cam);
-    let world = BvhNode::new(&mut world);
+    match args.file {
+        Some(ref path) => world.obj_file(path)?,
+        None => world.scene(&args.scene, &mut cam)?,
+    }
+    if let Some((a, b)) = args.smoke {
+        let density = args.smoke_density.unwrap_or(0.01);
+        world.add(ConstantMedium::cuboid(a, b, density, Color::repeat(args.smoke_albedo.unwrap_or(1.))));
+    }
+    if let Some(ref path) = args.volume {
+        let albedo = args.volume_albedo.unwrap_or(if args.temperature.is_some() { 0.5 } else { 1. });
+        let mut medium = GridMedium::new(VoxelGrid::load(path)?, args.volume_density.unwrap_or(1.), Color::repeat(albedo));
+        if let Some(ref path) = args.temperature {
+            medium = medium.with_fire(VoxelGrid::load(path)?, args.fire_brightness.unwrap_or(1.));
+        }
+        world.add(medium);
+    }
+    if let Some(fov) = args.fov { cam.fov = fov; }
+    cam.projection = parse_projection(&args.projection, &cam)?;
+    if let Some(density) = args.fog {
+        let default = Atmosphere::default();
+        cam.atmosphere = Some(Atmosphere {
+            density,
+            albedo: args.fog_albedo.map_or(default.albedo, Color::repeat),
+        });
+    }
+    let default = PhotonMapping::default();
+    let photons = PhotonMapping {
+        photons: args.photons.unwrap_or(default.photons),
+        radius: args.photon_radius.or(default.radius),
+        ..default
+    };
+    cam.integrator = parse_integrator(&args.integrator, &cam, photons)?;
+    if let Some(layout) = args.stereo {
+        let default = Stereo::default();
+        cam.stereo = Some(Stereo {
+            eye_separation: args.eye_separation.unwrap_or(default.eye_separation),
+            convergence: args.convergence,
+            layout,
+        });
+    }
+    if args.physical {
+        let default = PhysicalCamera::default();
+        cam.physical = Some(PhysicalCamera {
+            f_number: args.f_number.unwrap_or(default.f_number),
+            shutter_time: args.shutter_time.unwrap_or(default.shutter_time),
+            iso: args.iso.unwrap_or(default.iso),
+            exposure_compensation: args.ev.unwrap_or(default.exposure_compensation),
+            units_per_meter: args.units_per_meter.unwrap_or(default.units_per_meter),
+            ..default
+        });
+    }
+    if let Some(angle) = args.defocus_angle { cam.defocus_angle = angle; }
+    if args.focus_dist.is_some() { cam.focus_dist = args.focus_dist; }
+    if let Some(time) = args.shutter_open { cam.shutter_open = time; }
+    if let Some(time) = args.shutter_close { cam.shutter_close = time; }
+    cam.lights = world.lights();
+    let world = BvhNode::new(world);
 
-    cam.render_with_preview(&world)?;
+    #[cfg(feature = "preview")]
+    let framebuffer = match (args.headless, args.progressive) {
+        (true, true) => cam.render_progressive_no_preview(&world),
+        (true, false) => cam.render_no_preview(&world),
+        (false, true) => cam.render_progressive(&world)?,
+        (false, false) => cam.render_with_preview(&world)?,
+    };
+    #[cfg(not(feature = "preview"))]
+    let framebuffer = match args.progressive {
+        true => cam.render_progressive_no_preview(&world),
+        false => cam.render_no_preview(&world),
+    };
+
+    if args.adaptive {
+        let counts = framebuffer.sample_counts();
+        let average = counts.iter().map(|&n| n as f32).sum::<f32>() / counts.len() as f32;
+        eprintln!("Average samples per pixel: {:.1}", average);
+    }
+    framebuffer.save(&args.output)?;
+    if args.aovs {
+        cam.render_aovs(&world, args.spp).save(&args.output)?;
+    }
+    if let Some(path) = args.sample_map {
+        framebuffer.sample_count_map().save(path)?;
+    }
 
     Ok(())
 }
-
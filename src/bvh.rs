@@ -1,7 +1,7 @@
 use std::sync::Arc;
 use std::cmp::Ordering;
 
-use rand::Rng;
+use rand::{Rng, SeedableRng};
 
 use crate::{
     ray::{Ray, Intersect},
@@ -9,11 +9,14 @@ use crate::{
     hittable::{Hittable, HitRecord, Primitive},
     hittable_list::HittableList,
     aabb::AABB,
+    random::SampleRng,
+    sampler::Sampler,
 };
 
 #[derive(Clone)]
 pub enum BvhNode {
-    Leaf(Primitive),
+    /// Primitive and its object id
+    Leaf(Primitive, u32),
     Node {
         left:  Arc<BvhNode>,
         right: Arc<BvhNode>,
@@ -22,16 +25,27 @@ pub enum BvhNode {
 }
 
 impl BvhNode {
-    pub fn new(list: &mut HittableList<Primitive>) -> Self {
-        Self::from_vec(&mut list.objects)
+    pub fn new(list: HittableList<Primitive>) -> Self {
+        Self::from_vec(list.objects)
     }
 
-    pub fn from_vec(list: &mut [Primitive]) -> Self {
+    /// Object ids count from 1 in the order of `list`.
+    pub fn from_vec(list: Vec<Primitive>) -> Self {
+        // fixed seed, so the same scene always gets the same tree
+        let mut rng = SampleRng::seed_from_u64(0);
+        let mut objects: Vec<(u32, Primitive)> = list.into_iter()
+            .enumerate()
+            .map(|(index, object)| (index as u32 + 1, object))
+            .collect();
+        Self::build(&mut objects, &mut rng)
+    }
+
+    fn build(list: &mut [(u32, Primitive)], rng: &mut SampleRng) -> Self {
         use BvhNode::*;
         let start = 0;
         let end = list.len();
 
-        let axis = rand::thread_rng().gen_range(0..=2);
+        let axis = rng.gen_range(0..=2);
         let comparator = match axis { 
             0 => Self::box_x_compare,
             1 => Self::box_y_compare,
@@ -41,22 +55,18 @@ impl BvhNode {
         let object_span = end - start;
 
         let (left, right): (BvhNode, BvhNode) = match object_span {
-            1 => (Leaf(list[start].clone()), Leaf(list[start].clone())),
+            1 => return Self::leaf(&list[start]),
             2 => if comparator(&list[start], &list[start+1]).is_lt() {
-                    let left = list[start].clone();
-                    let right = list[start+1].clone();
-                    (Leaf(left), Leaf(right))
+                    (Self::leaf(&list[start]), Self::leaf(&list[start+1]))
                 } else {
-                    let left = list[start+1].clone();
-                    let right = list[start].clone();
-                    (Leaf(left), Leaf(right))
+                    (Self::leaf(&list[start+1]), Self::leaf(&list[start]))
                 },
             _ => {
                 list.sort_by(comparator);
 
                 let mid = start + object_span/2;
-                let left = Self::from_vec(&mut list[start..mid]);
-                let right = Self::from_vec(&mut list[mid..end]);
+                let left = Self::build(&mut list[start..mid], rng);
+                let right = Self::build(&mut list[mid..end], rng);
                 (left, right)
             }
         };
@@ -68,7 +78,57 @@ impl BvhNode {
         }
     }
 
-    fn box_compare(a: &Primitive, b: &Primitive, axis_index: usize) -> Ordering {
+    /// Every primitive in the tree, in the order of their object ids
+    pub(crate) fn primitives(&self) -> Vec<&Primitive> {
+        let mut leaves = vec!();
+        self.collect_leaves(&mut leaves);
+        leaves.sort_by_key(|&(id, _)| id);
+        leaves.into_iter().map(|(_, object)| object).collect()
+    }
+
+    fn collect_leaves<'a>(&'a self, leaves: &mut Vec<(u32, &'a Primitive)>) {
+        match self {
+            BvhNode::Leaf(object, id) => leaves.push((*id, object)),
+            BvhNode::Node { left, right, .. } => {
+                left.collect_leaves(leaves);
+                right.collect_leaves(leaves);
+            },
+        }
+    }
+
+    fn leaf((id, object): &(u32, Primitive)) -> Self {
+        BvhNode::Leaf(object.clone(), *id)
+    }
+
+    /// Closest hit that `hit_leaf` finds in the leaves whose boxes `r`
+    /// meets, adding the number of boxes tested to `cost`
+    fn traverse<F>(&self, r: &Ray, ray_t: Interval, cost: &mut u32, hit_leaf: &mut F) -> Option<HitRecord>
+    where
+        F: FnMut(&Primitive, Interval, &mut u32) -> Option<HitRecord>,
+    {
+        *cost += 1;
+        if !r.intersects(&self.bounding_box(), ray_t) { return None }
+
+        match self {
+            BvhNode::Node { left, right, .. } => {
+                let hit_left = left.traverse(r, ray_t, cost, hit_leaf);
+                let new_max = if let Some(hl) = &hit_left { hl.t } else {ray_t.max};
+                let hit_right = right.traverse(r, Interval::new(ray_t.min, new_max), cost, hit_leaf);
+
+                match hit_right {
+                    Some(_) => hit_right,
+                    None => hit_left,
+                }
+            },
+            BvhNode::Leaf(l, id) => {
+                let mut rec = hit_leaf(l, ray_t, cost)?;
+                rec.object_id = *id;
+                Some(rec)
+            },
+        }
+    }
+
+    fn box_compare((_, a): &(u32, Primitive), (_, b): &(u32, Primitive), axis_index: usize) -> Ordering {
         if a.bounding_box().axis(axis_index).min < b.bounding_box().axis(axis_index).min {
             Ordering::Less
         } else {
@@ -76,44 +136,49 @@ impl BvhNode {
         }
     }
 
-    fn box_x_compare(a: &Primitive, b: &Primitive) -> Ordering {
+    fn box_x_compare(a: &(u32, Primitive), b: &(u32, Primitive)) -> Ordering {
         Self::box_compare(a, b, 0)
     }
 
-    fn box_y_compare(a: &Primitive, b: &Primitive) -> Ordering {
+    fn box_y_compare(a: &(u32, Primitive), b: &(u32, Primitive)) -> Ordering {
         Self::box_compare(a, b, 1)
     }
 
-    fn box_z_compare(a: &Primitive, b: &Primitive) -> Ordering {
+    fn box_z_compare(a: &(u32, Primitive), b: &(u32, Primitive)) -> Ordering {
         Self::box_compare(a, b, 2)
     }
 }
 
 impl Hittable for BvhNode {
     fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
-        let bbox = self.bounding_box();
-
-        if !r.intersects(&bbox, ray_t) { return None }
+        self.hit_counting(r, ray_t, &mut 0)
+    }
 
+    fn bounding_box(&self) -> AABB {
         match self {
-            BvhNode::Node { left, right, .. } => {
-                let hit_left = left.hit(r, ray_t);
-                let new_max = if let Some(hl) = &hit_left { hl.t } else {ray_t.max};
-                let hit_right = right.hit(r, Interval::new(ray_t.min, new_max));
-
-                match hit_right {
-                    Some(_) => hit_right,
-                    None => hit_left,
-                }
-            },
-            BvhNode::Leaf(l) => l.hit(r, ray_t)
+            Self::Leaf(l, _) => l.bounding_box(),
+            Self::Node{ bbox, ..} => *bbox,
         }
     }
 
-    fn bounding_box(&self) -> AABB {
+    fn hit_counting(&self, r: &Ray, ray_t: Interval, cost: &mut u32) -> Option<HitRecord> {
+        self.traverse(r, ray_t, cost, &mut |l, ray_t, cost| l.hit_counting(r, ray_t, cost))
+    }
+
+    fn sample_hit(&self, r: &Ray, ray_t: Interval, sampler: &mut Sampler) -> Option<HitRecord> {
+        self.traverse(r, ray_t, &mut 0, &mut |l, ray_t, _| l.sample_hit(r, ray_t, sampler))
+    }
+
+    fn transmittance(&self, r: &Ray, ray_t: Interval, sampler: &mut Sampler) -> f32 {
+        if !r.intersects(&self.bounding_box(), ray_t) { return 1. }
+
         match self {
-            Self::Leaf(l) => l.bounding_box(),
-            Self::Node{ bbox, ..} => *bbox,
+            BvhNode::Node { left, right, .. } => {
+                let left = left.transmittance(r, ray_t, sampler);
+                if left == 0. { return 0. }
+                left * right.transmittance(r, ray_t, sampler)
+            },
+            BvhNode::Leaf(l, _) => l.transmittance(r, ray_t, sampler),
         }
     }
 }
@@ -1,19 +1,21 @@
-use anyhow::{Result, anyhow};
-use sdl2::EventPump;
-use sdl2::{
-    event::Event,
-    Sdl,
-    keyboard::Keycode,
-    pixels::PixelFormatEnum,
-    render::{WindowCanvas, Texture},
-};
-use crate::color::{write_color, linear_to_gamma};
 use crate::material::Material;
-use std::f32::INFINITY;
-use image::{ImageBuffer, RgbImage};
-use rand::Rng;
-use std::time::Instant;
+use crate::aov::{Aovs, AovPixel, aov_pixel};
+use crate::denoise::Denoiser;
+use crate::light::Lights;
+use crate::hittable::HitRecord;
+use crate::framebuffer::{Framebuffer, RenderedPixel, Splat};
+use crate::color::{DisplayTransform, luminance};
+use crate::tile::Tile;
+use crate::sampler::{Sampler, SamplerKind};
+use crate::photon::{PhotonMap, PhotonMapping, trace_photon};
+use crate::medium::{Atmosphere, Hazy};
 use rayon::prelude::*;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::f32::consts::PI;
+use std::ops::Range;
+use std::time::{Duration, Instant};
+use anyhow::Result;
 
 use crate::ray::Ray;
 use crate::interval::Interval;
@@ -22,6 +24,13 @@ use crate::vec3::{
 };
 use crate::{hittable::Hittable, color::Color};
 
+#[cfg(feature = "preview")]
+mod preview;
+mod bdpt;
+mod integrator;
+
+pub use integrator::{Integrator, PathTracer, Bidirectional, Whitted, Normals, Uv, AmbientOcclusion, TraversalCost};
+
 #[allow(dead_code)]
 #[derive(Default)]
 pub struct Camera {
@@ -30,11 +39,53 @@ pub struct Camera {
     pub image_height: u32,
     pub samples_per_pixel: u32,
     pub max_bounces: u32,
+    /// Bounces every path makes before Russian roulette may end it
+    pub roulette_bounces: u32,
     pub fov: f32,
     pub lookfrom: Point3,
     pub lookat: Point3,
     pub vup: Vec3,
     pub background: Color,
+    /// Fills the bounding box of the scene with haze if set, which shows
+    /// light shafts
+    pub atmosphere: Option<Atmosphere>,
+    /// Variation angle in degrees of rays through each pixel, 0 for a pinhole camera
+    pub defocus_angle: f32,
+    /// Distance from `lookfrom` to the plane in perfect focus, `lookat` if None
+    pub focus_dist: Option<f32>,
+    /// Projection model, only `Perspective` supports depth of field
+    pub projection: Projection,
+    /// Derives depth of field, motion blur and exposure from real camera
+    /// settings if set, replacing `defocus_angle` and `shutter_close`
+    pub physical: Option<PhysicalCamera>,
+    /// Exposure and tone mapping for the preview and 8 bit images
+    pub display: DisplayTransform,
+    /// Emitters sampled directly at diffuse surfaces, see [`HittableList::lights`](crate::HittableList::lights)
+    pub lights: Lights,
+    /// Algorithm that finds the light arriving along camera rays
+    pub integrator: Box<dyn Integrator>,
+    /// Filters the noise out of the final image and the progressive preview
+    pub denoiser: Option<Denoiser>,
+    /// Renders an image for each eye into one framebuffer if set
+    pub stereo: Option<Stereo>,
+    /// Scene time at which the shutter opens. Moving objects are at their
+    /// start position at time 0 and at their end position at time 1.
+    pub shutter_open: f32,
+    /// Scene time at which the shutter closes, equal to `shutter_open` for no motion blur
+    pub shutter_close: f32,
+    /// Edge length in pixels of the tiles the image is split into for rendering
+    pub tile_size: u32,
+    /// Progressive rendering stops after this long even if `samples_per_pixel`
+    /// has not been reached yet
+    pub time_budget: Option<Duration>,
+    /// Renders with the same seed and settings are identical
+    pub seed: u64,
+    /// How the samples of a pixel are distributed
+    pub sampler: SamplerKind,
+    /// Replaces the fixed `samples_per_pixel` with a per-pixel count chosen
+    /// from the noise of the pixel. Progressive rendering and the
+    /// bidirectional integrator ignore it.
+    pub adaptive: Option<AdaptiveSampling>,
 
     center: Point3,
     pixel00_loc: Point3,
@@ -43,8 +94,141 @@ pub struct Camera {
     u: Vec3,
     v: Vec3,
     w: Vec3,
+    defocus_disk_u: Vec3,
+    defocus_disk_v: Vec3,
+    convergence: f32,
+    viewport_shift: Vec3,
+    shutter_time: f32,
+    exposure: f32,
+    // sum of the splats of the bidirectional integrator
+    light_image: Mutex<Vec<Color>>,
+    // caustic photons of the current pass of the photon mapping integrator
+    photons: PhotonMap,
+}
+
+// Camera rays per pixel for the AOVs guiding the denoiser, enough to
+// antialias the edges
+const DENOISER_AOV_SAMPLES: u32 = 16;
+
+/// How rays leave the camera, see [`Camera::projection`]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Projection {
+    /// Pinhole or thin lens camera with a vertical field of view of `Camera::fov`
+    #[default]
+    Perspective,
+    /// Parallel rays along the view direction, `height` is the height of
+    /// the visible area in scene units
+    Orthographic { height: f32 },
+    /// Equidistant fisheye lens, the image circle spans `fov` degrees
+    /// across the image height
+    Fisheye { fov: f32 },
+    /// Full 360 by 180 degree panorama in latitude/longitude layout, meant
+    /// for images with an aspect ratio of 2:1
+    Equirectangular,
+}
+
+/// Settings of a real camera, see [`Camera::physical`]
+///
+/// Scene colors are taken as luminance in cd/m² and scene time in seconds.
+/// The focal length follows from `Camera::fov` and the sensor size.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PhysicalCamera {
+    /// Height of the sensor in mm, 24 for full frame
+    pub sensor_height: f32,
+    /// Aperture as the ratio of focal length to lens diameter
+    pub f_number: f32,
+    /// Time the shutter stays open in seconds, starting at `Camera::shutter_open`
+    pub shutter_time: f32,
+    /// Sensor sensitivity
+    pub iso: f32,
+    /// Extra exposure in stops on top of the one metered from the settings
+    pub exposure_compensation: f32,
+    /// Scene units per meter, to size the lens aperture
+    pub units_per_meter: f32,
+}
+
+impl Default for PhysicalCamera {
+    fn default() -> Self {
+        PhysicalCamera {
+            sensor_height: 24.,
+            f_number: 8.,
+            shutter_time: 1. / 125.,
+            iso: 100.,
+            exposure_compensation: 0.,
+            units_per_meter: 1.,
+        }
+    }
+}
+
+impl PhysicalCamera {
+    /// Focal length in mm giving a vertical field of view of `fov` degrees
+    pub fn focal_length(&self, fov: f32) -> f32 {
+        self.sensor_height / 2. / (fov.to_radians() / 2.).tan()
+    }
+
+    /// Radius of the lens opening in scene units
+    pub fn aperture_radius(&self, fov: f32) -> f32 {
+        self.focal_length(fov) / self.f_number / 2. / 1000. * self.units_per_meter
+    }
+
+    /// Factor from scene luminance to sensor value, using the saturation based
+    /// sensitivity with the usual lens and vignetting factor of 0.65
+    pub fn exposure(&self) -> f32 {
+        let ev100 = (self.f_number * self.f_number / self.shutter_time * 100. / self.iso).log2();
+        let max_luminance = 78. / (100. * 0.65) * 2f32.powf(ev100);
+        2f32.powf(self.exposure_compensation) / max_luminance
+    }
+}
+
+/// Settings for stereoscopic rendering, see [`Camera::stereo`]
+///
+/// Both eyes sit on the line through `lookfrom` along the camera's horizontal
+/// axis. Perspective eyes use off-axis frustums and orthographic eyes are
+/// turned towards each other so that objects at the convergence distance
+/// appear at the same place in both images, fisheye eyes look parallel.
+/// With the equirectangular projection the eyes circle around `lookfrom`
+/// for omni-directional stereo.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stereo {
+    /// Distance between the eyes in scene units
+    pub eye_separation: f32,
+    /// Distance of zero parallax, the distance to `lookat` if None
+    pub convergence: Option<f32>,
+    pub layout: StereoLayout,
+}
+
+/// Placement of the two eye images in the framebuffer
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum StereoLayout {
+    /// Left eye on the left, right eye on the right
+    #[default]
+    SideBySide,
+    /// Left eye on top, right eye below
+    OverUnder,
+}
+
+impl Default for Stereo {
+    fn default() -> Self {
+        Stereo { eye_separation: 0.065, convergence: None, layout: StereoLayout::default() }
+    }
+}
+
+/// Settings for adaptive sampling, see [`Camera::adaptive`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdaptiveSampling {
+    /// Samples every pixel gets before its noise is judged
+    pub min_samples: u32,
+    /// Upper limit for pixels that do not converge
+    pub max_samples: u32,
+    /// A pixel is done once the standard error of its mean luminance
+    /// falls below this fraction of the mean
+    pub max_error: f32,
+}
 
-    imgbuf: RgbImage,
+impl Default for AdaptiveSampling {
+    fn default() -> Self {
+        AdaptiveSampling { min_samples: 16, max_samples: 256, max_error: 0.05 }
+    }
 }
 
 impl Camera {
@@ -53,29 +237,50 @@ impl Camera {
         let image_height = ((image_width as f32 / aspect_ratio) as u32).max(1);
         let samples_per_pixel = 16;
         let max_bounces = 10;
+        let roulette_bounces = 3;
 
         let fov: f32 = 80.;
         let lookfrom = Point3::new(9.0,0.0,0.);
         let lookat   = Point3::new(0.,0.,0.);
         let vup      =   Vec3::new(0.,1.,0.);
 
-        let imgbuf: RgbImage = ImageBuffer::new(image_width, image_height);
         let background = Color::new(0.7, 0.8, 1.0);
+        let tile_size = 16;
 
         Camera {
             aspect_ratio, image_width,
             image_height,
             samples_per_pixel,
-            max_bounces, fov, lookfrom, lookat,
-            vup, imgbuf, background, ..Default::default()
+            max_bounces, roulette_bounces, fov, lookfrom, lookat,
+            vup, background, tile_size, ..Default::default()
         }
     }
     
+    /// Size of the rendered image, which holds both eyes for stereo rendering
+    pub fn output_size(&self) -> (u32, u32) {
+        match self.stereo.map(|stereo| stereo.layout) {
+            None => (self.image_width, self.image_height),
+            Some(StereoLayout::SideBySide) => (2 * self.image_width, self.image_height),
+            Some(StereoLayout::OverUnder) => (self.image_width, 2 * self.image_height),
+        }
+    }
+
+    fn framebuffer(&self) -> Framebuffer {
+        let (width, height) = self.output_size();
+        let mut framebuffer = Framebuffer::new(width, height);
+        framebuffer.set_display(self.display);
+        framebuffer
+    }
+
     fn update(&mut self) {
-        let focal_length = (self.lookfrom - self.lookat).norm();
+        let focus_dist = self.focus_dist
+            .unwrap_or_else(|| (self.lookfrom - self.lookat).norm());
         let theta = self.fov.to_radians();
         let h = (theta/2.0).tan();
-        let viewport_height = 2.0 * h * focal_length;
+        let viewport_height = match self.projection {
+            Projection::Orthographic { height } => height,
+            _ => 2.0 * h * focus_dist,
+        };
         let viewport_width = viewport_height *
             (self.image_width as f32/self.image_height as f32);
         self.center = self.lookfrom;
@@ -93,171 +298,666 @@ impl Camera {
         self.pixel_delta_u = viewport_u / self.image_width as f32;
         self.pixel_delta_v = viewport_v / self.image_height as f32;
 
-        // Upper left pixel
+        // Upper left pixel, orthographic rays start on the plane through the camera
+        let viewport_distance = match self.projection {
+            Projection::Orthographic { .. } => 0.,
+            _ => focus_dist,
+        };
         let viewport_upper_left = self.center -
-            focal_length*self.w - viewport_u/2. - viewport_v/2.;
+            viewport_distance*self.w - viewport_u/2. - viewport_v/2.;
         self.pixel00_loc = viewport_upper_left +
             0.5 * (self.pixel_delta_u+self.pixel_delta_v);
-    }
-
-    pub fn render_with_preview<T: Hittable+Sync>(&mut self, world: &T)
-        -> Result<()> {
-
-            // init SDL
-            let (sdl_context, canvas) = self.setup_sdl()?;
-
-            let mut event_pump = sdl_context
-                .event_pump()
-                .map_err(|e| anyhow!(e))?;
-            let texture_creator = canvas.texture_creator();
-            let texture = texture_creator
-                .create_texture_streaming(PixelFormatEnum::RGB24, self.image_width, self.image_height)?;
-
-            // Measure time
-            eprintln!("Starting render");
-            let start = Instant::now();
-
-            self.preview_render_loop(world, texture, canvas, &mut event_pump)?;
-
-            eprintln!("\r Done.                   ");
-            let duration = start.elapsed();
-            eprintln!("Render took {:.2?}", duration);
 
-            self.imgbuf.save("image.png").unwrap();
+        // Stereo eyes, per unit of eye offset
+        self.convergence = self.stereo
+            .and_then(|stereo| stereo.convergence)
+            .unwrap_or_else(|| (self.lookfrom - self.lookat).norm());
+        self.viewport_shift = (1. - focus_dist / self.convergence) * self.u;
+
+        // Camera defocus disk, shutter and exposure
+        let defocus_radius = match self.physical {
+            Some(physical) => physical.aperture_radius(self.fov),
+            None => focus_dist * (self.defocus_angle / 2.).to_radians().tan(),
+        };
+        self.shutter_time = match self.physical {
+            Some(physical) => physical.shutter_time,
+            None => self.shutter_close - self.shutter_open,
+        };
+        self.exposure = self.physical.map_or(1., |physical| physical.exposure());
+        self.defocus_disk_u = defocus_radius * self.u;
+        self.defocus_disk_v = defocus_radius * self.v;
+
+        let (width, height) = self.output_size();
+        self.light_image = Mutex::new(vec![Color::zeros(); (width * height) as usize]);
+    }
 
-            Ok(())
+    pub fn render_no_preview<T: Hittable+Sync>(&mut self, world: &T) -> Framebuffer {
+        if self.integrator.photon_mapping().is_some() {
+            return self.render_progressive_no_preview(world)
         }
-
-    pub fn render_no_preview<T: Hittable+Sync>(&mut self, world: &T) -> Result<()> {
+        let aovs = self.denoiser_aovs(world);
         self.update();
+        let mut framebuffer = self.framebuffer();
+
+        let tiles = self.tiles();
+        let remaining = AtomicUsize::new(tiles.len());
+        let rendered: Vec<(Tile, Vec<RenderedPixel>, Vec<Splat>)> = tiles.into_par_iter()
+            .map(|tile| {
+                let (colors, splats) = self.render_tile(&tile, world);
+                let left = remaining.fetch_sub(1, Ordering::Relaxed) - 1;
+                eprint!{"\rTiles remaining: {} ", left};
+                (tile, colors, splats)
+            })
+            .collect();
+        eprintln!();
+
+        for (tile, colors, splats) in rendered {
+            framebuffer.write_tile(&tile, &colors);
+            self.add_splats(&splats);
+        }
+        let framebuffer = self.with_light_image(framebuffer, 1. / self.samples_per_pixel.max(1) as f32);
+        Self::denoised(self.denoiser, framebuffer, aovs.as_ref())
+    }
 
-        for j in 0..self.image_height {
-            eprint!{"\rScanlines remaining: {} ", (self.image_height - j)};
-
-            for i in 0..self.image_width {
-                let mut pixel_color = (0..self.samples_per_pixel).into_iter()
-                    .map(|_| {
-                        let r = self.get_ray(i,j);
-                        self.ray_color(&r, self.max_bounces, world)
-                    })
-                .sum::<Vec3>();
-                pixel_color /= self.samples_per_pixel as f32;
+    /// Headless counterpart of `render_progressive`: accumulates one sample
+    /// per pixel passes until `samples_per_pixel` or the `time_budget` is reached.
+    pub fn render_progressive_no_preview<T: Hittable+Sync>(&mut self, world: &T) -> Framebuffer {
+        self.progressive_passes(world, |_, _| Ok(true))
+            .expect("headless passes cannot fail")
+    }
 
-                write_color(i, j, &mut self.imgbuf, pixel_color);
+    /// Traces only the camera rays, `samples` per pixel with the same
+    /// sample positions as the image, and averages what they hit first.
+    pub fn render_aovs<T: Hittable+Sync>(&mut self, world: &T, samples: u32) -> Aovs {
+        self.update();
+        let (width, height) = self.output_size();
+        let rendered: Vec<(Tile, Vec<AovPixel>)> = self.tiles().into_par_iter()
+            .map(|tile| {
+                let pixels = tile.pixels()
+                    .map(|(i, j)| self.aov_pixel(i, j, samples.max(1), world))
+                    .collect();
+                (tile, pixels)
+            })
+            .collect();
+
+        let mut aovs = Aovs::new(width, height);
+        for (tile, pixels) in rendered {
+            for ((i, j), pixel) in tile.pixels().zip(&pixels) {
+                aovs.set(i, j, pixel);
             }
         }
-        self.imgbuf.save("image.png").unwrap();
-        Ok(())
+        aovs
     }
 
     // private
-    fn preview_render_loop<T: Hittable+Sync>(&mut self, world: &T, mut texture: Texture<'_>, mut canvas: WindowCanvas, event_pump: &mut EventPump)
-        -> Result<()> {
+    /// Runs one sample per pixel passes over the whole image and returns their
+    /// (denoised) average. After every pass `after_pass` gets the current
+    /// average and the AOVs for the denoiser and can return false to stop early.
+    fn progressive_passes<T, F>(&mut self, world: &T, mut after_pass: F) -> Result<Framebuffer>
+        where T: Hittable+Sync, F: FnMut(&Framebuffer, Option<&Aovs>) -> Result<bool> {
+        let aovs = self.denoiser_aovs(world);
         self.update();
+        let mut accumulated = self.framebuffer();
+        let deadline = self.time_budget.map(|budget| Instant::now() + budget);
+        let tiles = self.tiles();
+        let mut passes = 0;
+
+        while passes < self.samples_per_pixel {
+            if let Some(settings) = self.integrator.photon_mapping() {
+                self.photons = self.photon_map(world, settings, passes);
+            }
+            let rendered: Vec<_> = tiles.par_iter()
+                .map(|tile| (tile, self.render_tile_samples(tile, passes..passes+1, world)))
+                .collect();
+            for (tile, (colors, splats)) in rendered {
+                accumulated.accumulate_tile(tile, &colors);
+                self.add_splats(&splats);
+            }
+            passes += 1;
+            eprint!{"\rSamples per pixel: {} ", passes};
 
-        'rendering: {
-            for j in 0..self.image_height {
-                eprint!{"\rScanlines remaining: {} ", (self.image_height - j)};
-
-                texture.with_lock(None, |buffer, pitch| {
-                    for i in 0..self.image_width {
-                        let mut pixel_color = (0..self.samples_per_pixel).into_par_iter()
-                            .map(|_| {
-                                let r = self.get_ray(i,j);
-                                self.ray_color(&r, self.max_bounces, world)
-                            })
-                        .sum::<Vec3>();
-                        pixel_color /= self.samples_per_pixel as f32;
-
-                        write_color(i, j, &mut self.imgbuf, pixel_color);
-
-                        Self::write_to_buffer(i, j, buffer, pitch, pixel_color);
-                    }
-                })
-                .map_err(|e| anyhow!(e))?;
-                canvas.clear();
-                canvas.copy(&texture, None, None).map_err(|e| anyhow!(e))?;
-                canvas.present();
-                if Self::poll_quit(event_pump) {
-                    eprintln!(); break 'rendering
-                }
+            let average = self.with_light_image(accumulated.scaled(1. / passes as f32), 1. / passes as f32);
+            if !after_pass(&average, aovs.as_ref())? {
+                break
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                eprint!("(time budget used up)"); break
             }
-            loop { if Self::poll_quit(event_pump) { break 'rendering }}
         }
+        eprintln!();
 
-        Ok(())
+        let scale = 1. / passes.max(1) as f32;
+        let average = self.with_light_image(accumulated.scaled(scale), scale);
+        Ok(Self::denoised(self.denoiser, average, aovs.as_ref()))
     }
 
-    fn setup_sdl(&self) -> Result<(Sdl, WindowCanvas)> {
-        let sdl_context = sdl2::init()
-            .map_err(|e| anyhow!(e))?;
-        let video_subsystem = sdl_context.video()
-            .map_err(|e| anyhow!(e))?;
+    /// Caustic photons for pass `pass`, gathered within the radius of that pass
+    fn photon_map<T: Hittable+Sync>(&self, world: &T, settings: &PhotonMapping, pass: u32) -> PhotonMap {
+        let photons = (0..settings.photons).into_par_iter()
+            .filter_map(|index| {
+                // a stream of its own, apart from the camera samples
+                let mut sampler = Sampler::new(SamplerKind::Independent, self.seed,
+                    (index, pass), u32::MAX - 1, 1);
+                let time = self.shutter_open + sampler.next_1d() * self.shutter_time;
+                match self.atmosphere {
+                    Some(ref atmosphere) => trace_photon(&Hazy::new(world, atmosphere), &self.lights, time,
+                        self.max_bounces, &mut sampler),
+                    None => trace_photon(world, &self.lights, time, self.max_bounces, &mut sampler),
+                }
+            })
+            .collect();
+        let initial = settings.radius.unwrap_or_else(|| {
+            let bbox = world.bounding_box();
+            let diagonal = Vec3::from_fn(|a, _| bbox.axis(a).max - bbox.axis(a).min).norm();
+            diagonal / 200.
+        });
+        PhotonMap::new(photons, settings.photons, settings.radius(initial, pass))
+    }
 
-        let window = video_subsystem
-            .window("Raytracer", self.image_width, self.image_height)
-            .build()?;
-        let canvas = window.into_canvas().build()?;
-        Ok((sdl_context, canvas))
+    /// First-hit AOVs to guide the denoiser, None without denoiser
+    fn denoiser_aovs<T: Hittable+Sync>(&mut self, world: &T) -> Option<Aovs> {
+        self.denoiser?;
+        Some(self.render_aovs(world, self.samples_per_pixel.min(DENOISER_AOV_SAMPLES)))
     }
 
-    fn poll_quit(event_pump: &mut EventPump) -> bool {
-        for event in event_pump.poll_iter(){
-            match event {
-                Event::Quit { .. }
-                | Event::KeyDown { keycode: Some(Keycode::Escape), .. }
-                | Event::KeyDown { keycode: Some(Keycode::Q), ..}
-                => return true,
-                _ => {}
+    fn denoised(denoiser: Option<Denoiser>, image: Framebuffer, aovs: Option<&Aovs>) -> Framebuffer {
+        match (denoiser, aovs) {
+            (Some(denoiser), Some(aovs)) => denoiser.denoise(&image, aovs),
+            _ => image,
+        }
+    }
+
+    /// `image` plus the splats of the bidirectional integrator times `scale`
+    fn with_light_image(&self, mut image: Framebuffer, scale: f32) -> Framebuffer {
+        if !self.integrator.splats() { return image }
+        let light_image = self.light_image.lock().expect("no panics while holding the lock");
+        for j in 0..image.height() {
+            for i in 0..image.width() {
+                let splatted = light_image[(j * image.width() + i) as usize];
+                image.set(i, j, image.get(i, j) + scale * splatted);
             }
         }
-        false
+        image
     }
-    fn write_to_buffer(i: u32, j: u32, buffer: &mut [u8], pitch: usize, color: Color) {
-        let color = linear_to_gamma(color);
-        let offset: usize = (j*pitch as u32 + i*3) as usize;
 
-        buffer[offset] = (256.*color.x) as u8;
-        buffer[offset + 1] = (256.*color.y) as u8;
-        buffer[offset + 2] = (256.*color.z) as u8;
+    /// Adds to the light image, in the same order whatever the number of
+    /// threads, since floating point sums depend on it
+    fn add_splats(&self, splats: &[Splat]) {
+        if splats.is_empty() { return }
+        let (width, _) = self.output_size();
+        let mut light_image = self.light_image.lock().expect("no panics while holding the lock");
+        for (i, j, color) in splats {
+            light_image[(j * width + i) as usize] += color;
+        }
     }
-    fn get_ray(&self, i: u32, j: u32) -> Ray {
-        let pixel_center = self.pixel00_loc +
-            (i as f32*self.pixel_delta_u) + (j as f32*self.pixel_delta_v);
-        let pixel_sample = pixel_center + self.sample_loc();
 
-        let ray_origin = self.center;
-        let ray_direction = pixel_sample - ray_origin;
+    fn tiles(&self) -> Vec<Tile> {
+        let (width, height) = self.output_size();
+        Tile::split(width, height, self.tile_size)
+    }
 
-        Ray::new(ray_origin, ray_direction)
+    /// Final pixels of the tile, using adaptive sampling if enabled, and the
+    /// splats its samples left for the light image
+    fn render_tile<T: Hittable+Sync>(&self, tile: &Tile, world: &T) -> (Vec<RenderedPixel>, Vec<Splat>) {
+        match self.adaptive {
+            Some(adaptive) if !self.integrator.splats() => (tile.pixels()
+                .map(|(i, j)| self.render_pixel_adaptive(i, j, &adaptive, world))
+                .collect(), vec![]),
+            _ => self.render_tile_samples(tile, 0..self.samples_per_pixel, world),
+        }
     }
 
-    fn sample_loc(&self) -> Vec3 {
-        let px = -0.5 + rand::thread_rng().gen::<f32>();
-        let py = -0.5 + rand::thread_rng().gen::<f32>();
+    /// Average color of the given samples for every pixel in the tile, and
+    /// the splats they left for the light image
+    fn render_tile_samples<T: Hittable+Sync>(&self, tile: &Tile, samples: Range<u32>, world: &T) -> (Vec<RenderedPixel>, Vec<Splat>) {
+        let count = samples.len() as u32;
+        let mut splats = vec![];
+        let pixels = tile.pixels()
+            .map(|(i, j)| {
+                let pixel_color = samples.clone()
+                    .map(|sample| self.sample_pixel(i, j, sample, self.samples_per_pixel, world, &mut splats))
+                    .sum::<Vec3>();
+                (pixel_color / count as f32, count)
+            })
+            .collect();
+        (pixels, splats)
+    }
 
-        (px * self.pixel_delta_u) + (py * self.pixel_delta_v)
+    /// Keeps sampling the pixel until the standard error of its luminance is
+    /// small enough or `max_samples` is reached.
+    fn render_pixel_adaptive<T: Hittable+Sync>(&self, i: u32, j: u32, adaptive: &AdaptiveSampling, world: &T) -> RenderedPixel {
+        let max_samples = adaptive.max_samples.max(1);
+        let mut sum = Color::zeros();
+        // Welford's online mean and variance of the luminance
+        let (mut mean, mut m2) = (0., 0.);
+        let mut n = 0;
+        while n < max_samples {
+            // the path tracer leaves no splats
+            let color = self.sample_pixel(i, j, n, max_samples, world, &mut vec![]);
+            sum += color;
+            n += 1;
+
+            let y = luminance(color);
+            let delta = y - mean;
+            mean += delta / n as f32;
+            m2 += delta * (y - mean);
+
+            // only judge at doubling sample counts, stopping at any sample
+            // would favour pixels whose first samples happen to agree
+            if n >= adaptive.min_samples.max(2) && n.is_power_of_two() {
+                let std_error = (m2 / ((n - 1) * n) as f32).sqrt();
+                if std_error <= adaptive.max_error * mean.max(0.01) { break }
+            }
+        }
+        (sum / n as f32, n)
     }
 
-    fn ray_color<T: Hittable + Sync>(&self, r: &Ray, depth: u32, world: &T) -> Color {
-        // If the ray bounce limit has been exceeded, we return black
-        if depth == 0 { return Color::new(0.,0.,0.) }
+    fn aov_pixel<T: Hittable+Sync>(&self, i: u32, j: u32, samples: u32, world: &T) -> AovPixel {
+        let mut sum = AovPixel::default();
+        for sample in 0..samples {
+            let mut sampler = Sampler::new(self.sampler, self.seed, (i, j), sample, samples);
+            let hit = self.get_ray(i, j, &mut sampler)
+                .and_then(|r| Some((world.hit(&r, Interval::new(0.001, f32::INFINITY))?, r)));
+            let Some((rec, r)) = hit else { continue };
+
+            // ids cannot be averaged, the last layer keeps the first one
+            let pixel = aov_pixel(&r, &rec);
+            for (layer, value) in sum.iter_mut().zip(pixel).take(5) {
+                *layer += value / samples as f32;
+            }
+            if sample == 0 {
+                sum[5] = pixel[5];
+            }
+        }
+        sum
+    }
 
-        if let Some(hit) = world.hit(r, Interval::new(0.001, INFINITY)) {
-            let default = Material::default();
+    /// Color of one sample of the pixel, splats on other pixels are added to `splats`
+    fn sample_pixel<T: Hittable+Sync>(&self, i: u32, j: u32, sample: u32, samples_per_pixel: u32, world: &T, splats: &mut Vec<Splat>) -> Color {
+        let mut sampler = Sampler::new(self.sampler, self.seed, (i, j),
+            sample, samples_per_pixel);
+        let Some(r) = self.get_ray(i, j, &mut sampler) else { return Color::zeros() };
+        let first = splats.len();
+        let color = match self.atmosphere {
+            Some(ref atmosphere) => self.integrator.radiance(self, &r, &Hazy::new(world, atmosphere), &mut sampler, splats),
+            None => self.integrator.radiance(self, &r, world, &mut sampler, splats),
+        };
+        for (_, _, splat) in &mut splats[first..] {
+            *splat *= self.exposure;
+        }
+        self.exposure * color
+    }
+
+    /// Position of an output pixel in its eye's image and the offset of
+    /// that eye from `center` along `u`
+    fn eye_pixel(&self, i: u32, j: u32) -> (u32, u32, f32) {
+        let Some(stereo) = self.stereo else { return (i, j, 0.) };
+        let (i, j, right) = match stereo.layout {
+            StereoLayout::SideBySide => (i % self.image_width, j, i >= self.image_width),
+            StereoLayout::OverUnder => (i, j % self.image_height, j >= self.image_height),
+        };
+        let offset = stereo.eye_separation / 2.;
+        (i, j, if right { offset } else { -offset })
+    }
+
+    fn get_ray(&self, i: u32, j: u32, sampler: &mut Sampler) -> Option<Ray> {
+        let (i, j, eye_offset) = self.eye_pixel(i, j);
+        let eye = self.center + eye_offset*self.u;
+
+        // sample position in pixel units, (0, 0) is the upper left image corner
+        let (px, py) = sampler.next_2d();
+        let (x, y) = (i as f32 + px, j as f32 + py);
+        let (width, height) = (self.image_width as f32, self.image_height as f32);
+
+        let (ray_origin, ray_direction) = match self.projection {
+            Projection::Perspective => {
+                let pixel_sample = self.pixel00_loc + eye_offset*self.viewport_shift +
+                    ((x-0.5)*self.pixel_delta_u) + ((y-0.5)*self.pixel_delta_v);
+                let ray_origin = if self.defocus_disk_u == Vec3::zeros() {
+                    eye
+                } else {
+                    self.defocus_disk_sample(eye, sampler)
+                };
+                (ray_origin, pixel_sample - ray_origin)
+            },
+            Projection::Orthographic { .. } => {
+                let ray_origin = self.pixel00_loc + eye_offset*self.u +
+                    ((x-0.5)*self.pixel_delta_u) + ((y-0.5)*self.pixel_delta_v);
+                (ray_origin, -self.w - (eye_offset / self.convergence)*self.u)
+            },
+            Projection::Fisheye { fov } => {
+                // equidistant: the angle to the view direction grows linearly
+                // with the distance to the image center
+                let (nx, ny) = ((2.*x - width) / height, (2.*y - height) / height);
+                let r = (nx*nx + ny*ny).sqrt();
+                if r > 1. { return None }
+                let theta = r * fov.to_radians() / 2.;
+                let radial = if r > 0. { (nx*self.u - ny*self.v) / r } else { Vec3::zeros() };
+                (eye, theta.cos()*-self.w + theta.sin()*radial)
+            },
+            Projection::Equirectangular => {
+                let longitude = (x/width - 0.5) * 2. * PI;
+                let latitude = (0.5 - y/height) * PI;
+                let horizontal = longitude.sin()*self.u - longitude.cos()*self.w;
+                // omni-directional stereo: the eyes sit on a circle, to the
+                // side of every viewing direction
+                let side = longitude.cos()*self.u + longitude.sin()*self.w;
+                (self.center + eye_offset*side,
+                 latitude.cos()*horizontal + latitude.sin()*self.v)
+            },
+        };
+        let ray_time = self.shutter_open +
+            sampler.next_1d() * self.shutter_time;
+
+        Some(Ray::new(ray_origin, ray_direction, ray_time))
+    }
+
+    fn defocus_disk_sample(&self, center: Point3, sampler: &mut Sampler) -> Point3 {
+        let (x, y) = sampler.unit_disk();
+        center + x*self.defocus_disk_u + y*self.defocus_disk_v
+    }
+
+    /// Follows a path from the camera, adding up the light found at every
+    /// bounce weighted by the path throughput so far. Caustics come from
+    /// `photons` instead of hitting the lights if given.
+    fn ray_color<T: Hittable + Sync + ?Sized>(&self, r: &Ray, world: &T, sampler: &mut Sampler,
+        photons: Option<&PhotonMap>) -> Color {
+        let default = Material::default();
+        let mut radiance = Color::zeros();
+        let mut throughput = Color::new(1., 1., 1.);
+        let mut ray = *r;
+        // pdf with which the previous surface scattered `ray`, None for
+        // camera rays and specular bounces
+        let mut scatter_pdf: Option<f32> = None;
+        // whether the last bounce off anything but glass and mirrors was
+        // off a surface, and whether the path went only through glass or
+        // off mirrors since
+        let (mut diffuse, mut caustic) = (false, false);
+
+        for bounce in 0..self.max_bounces {
+            let Some(hit) = world.sample_hit(&ray, Interval::new(0.001, f32::INFINITY), sampler) else {
+                radiance += throughput.component_mul(&self.background);
+                break
+            };
             let material = match hit.material {
                 Some(ref mat) => mat,
                 None => &default,
             };
 
-            return match material.scatter(r, &hit) {
-                (attenuation, Some(scattered)) =>
-                    attenuation.component_mul(&self.ray_color(&scattered, depth-1, world)),
-                (attenuation, None) => attenuation,
+            let emitted = material.emitted();
+            if material.is_volume() {
+                // glowing media add their light wherever paths scatter in them
+                radiance += throughput.component_mul(&emitted);
+            } else if emitted != Color::zeros() {
+                // the photons already brought this light
+                if photons.is_some() && caustic { break }
+                // light sampling at the previous hit may have found this light too
+                let weight = match scatter_pdf {
+                    Some(pdf) => power_heuristic(pdf, self.lights.pdf(&ray, &hit)),
+                    None => 1.,
+                };
+                radiance += weight * throughput.component_mul(&emitted);
+                break
+            }
+
+            if !material.is_specular() {
+                let direct = self.sample_light(&ray, &hit, material, world, sampler, true);
+                radiance += throughput.component_mul(&direct);
+                if let Some(photons) = photons.filter(|_| !material.is_volume()) {
+                    let caustics = photons.radiance(&ray, &hit, material);
+                    radiance += throughput.component_mul(&caustics);
+                }
+            }
+            caustic = diffuse && material.is_specular();
+            if !material.is_specular() { diffuse = !material.is_volume() }
+
+            let Some(bsdf) = material.sample(&ray, &hit, sampler) else { break };
+            throughput.component_mul_assign(&bsdf.weight);
+
+            // Russian roulette: end dim paths early, but boost the surviving
+            // ones by the same factor so that the result stays unbiased
+            if bounce >= self.roulette_bounces {
+                let survival = throughput.max().min(0.95);
+                if sampler.next_1d() >= survival { break }
+                throughput /= survival;
             }
+
+            ray = bsdf.ray;
+            scatter_pdf = bsdf.pdf;
         }
+        radiance
+    }
 
-        self.background
+    /// Light reaching a surface straight from a randomly picked emitter,
+    /// weighted against finding the emitter by sampling the material if `mis`
+    fn sample_light<T: Hittable + Sync + ?Sized>(&self, r: &Ray, hit: &HitRecord, material: &Material, world: &T,
+        sampler: &mut Sampler, mis: bool) -> Color {
+        let Some(light) = self.lights.sample(hit.p, r.time(), sampler) else {
+            return Color::zeros()
+        };
+        let cosine = match material.is_volume() {
+            true => 1.,
+            false => light.direction.dot(&hit.normal),
+        };
+        if cosine <= 0. { return Color::zeros() }
+
+        // stop short of the light itself
+        let shadow_ray = Ray::new(hit.p, light.direction, r.time());
+        let transmittance = world.transmittance(&shadow_ray, Interval::new(0.001, light.distance * (1. - 1e-4)), sampler);
+        if transmittance == 0. { return Color::zeros() }
+
+        let bsdf = material.eval(r, hit, light.direction);
+        let weight = match mis {
+            true => power_heuristic(light.pdf, material.pdf(r, hit, light.direction)),
+            false => 1.,
+        };
+        (weight * transmittance * cosine / light.pdf) * bsdf.component_mul(&light.emitted)
     }
 }
 
+/// Multiple importance sampling weight of a sample drawn with pdf `a`
+/// that another strategy would have drawn with pdf `b`
+fn power_heuristic(a: f32, b: f32) -> f32 {
+    let (a, b) = (a*a, b*b);
+    if a + b > 0. { a / (a + b) } else { 1. }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use rayon::ThreadPoolBuilder;
+    use crate::{HittableList, BvhNode};
+    use super::*;
+
+    fn render_spheres(seed: u64, threads: usize) -> (Framebuffer, Vec<f32>) {
+        let mut cam = Camera::new(16.0/9.0, 48);
+        cam.samples_per_pixel = 4;
+        cam.max_bounces = 5;
+        cam.seed = seed;
+        let mut world = HittableList::new();
+        world.random_spheres(&mut cam);
+        let layout = world.objects.iter().flat_map(|object| {
+            let bbox = object.bounding_box();
+            [bbox.x.min, bbox.y.min, bbox.z.min]
+        }).collect();
+        let world = BvhNode::new(world);
+
+        let pool = ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+        (pool.install(|| cam.render_no_preview(&world)), layout)
+    }
+
+    #[test]
+    fn same_seed_same_image() {
+        let (single, layout) = render_spheres(7, 1);
+        let (multi, _) = render_spheres(7, 4);
+        assert!(single == multi, "Renders with the same seed must not depend on the thread count");
+
+        let (other, other_layout) = render_spheres(8, 4);
+        assert!(layout == other_layout, "The seed must not change the scene");
+        assert!(single != other, "Different seeds should give different noise");
+        let mean = |image: &Framebuffer| image.pixels().iter().sum::<Color>().sum() / image.pixels().len() as f32;
+        assert!((mean(&single) / mean(&other) - 1.).abs() < 0.05, "{} and {}", mean(&single), mean(&other));
+    }
+
+    #[test]
+    fn sunny_sixteen_exposure() {
+        // f/16, 1/100 s at ISO 100 is the classic exposure for sunlight,
+        // which maps a luminance of about 30000 cd/m² to white
+        let physical = PhysicalCamera { f_number: 16., shutter_time: 0.01, ..Default::default() };
+        let white = 1. / physical.exposure();
+        assert!((white - 30720.).abs() < 30., "got {}", white);
+
+        let brighter = PhysicalCamera { exposure_compensation: 1., ..physical };
+        assert!((brighter.exposure() / physical.exposure() - 2.).abs() < 1e-4);
+    }
+
+    #[test]
+    fn aov_object_ids() {
+        let mut cam = Camera::new(16.0/9.0, 48);
+        cam.seed = 1;
+        let mut world = HittableList::new();
+        world.random_spheres(&mut cam);
+        let objects = world.objects.len() as f32;
+        let world = BvhNode::new(world);
+
+        let aovs = cam.render_aovs(&world, 4);
+        for id in aovs.object_id.pixels().iter().map(|p| p.x) {
+            assert!(id.fract() == 0. && id <= objects, "{} is not an object id", id);
+        }
+        assert!(aovs.depth.pixels().iter().any(|p| p.x > 0.), "Camera rays must hit the scene");
+    }
+
+    #[test]
+    fn light_sampling_unbiased() {
+        use crate::{material::Material, quad::Quad, sphere::Sphere, texture::Texture};
+
+        let render = |light_sampling: bool| {
+            let mut world = HittableList::new();
+            let white: Material = Texture::new_solid_rgb(0.8, 0.8, 0.8).into();
+            world.add(Quad::new(Point3::new(-5., 0., -5.), Vec3::new(10., 0., 0.), Vec3::new(0., 0., 10.), white));
+            world.add(Sphere::new(Point3::new(0., 2., 0.), 0.5, Material::Emissive(Color::new(1., 1., 1.), 4.)));
+
+            let mut cam = Camera::new(1., 16);
+            cam.lookfrom = Point3::new(0., 3., 6.);
+            cam.lookat = Point3::new(0., 0., 0.);
+            cam.fov = 60.;
+            cam.samples_per_pixel = 256;
+            cam.max_bounces = 4;
+            cam.background = Color::zeros();
+            if light_sampling { cam.lights = world.lights(); }
+            let world = BvhNode::new(world);
+            let image = cam.render_no_preview(&world);
+            image.pixels().iter().map(|p| p.x).sum::<f32>() / image.pixels().len() as f32
+        };
+
+        let (with, without) = (render(true), render(false));
+        assert!((with / without - 1.).abs() < 0.05, "mean brightness {} with and {} without light sampling", with, without);
+    }
+
+    #[test]
+    fn list_and_bvh_agree() {
+        use crate::{material::Material, quad::Quad, sphere::Sphere, texture::Texture};
+
+        let mut world = HittableList::new();
+        let white: Material = Texture::new_solid_rgb(0.8, 0.8, 0.8).into();
+        world.add(Quad::new(Point3::new(-5., 0., -5.), Vec3::new(10., 0., 0.), Vec3::new(0., 0., 10.), white));
+        world.add(Sphere::new(Point3::new(0., 2., 0.), 0.5, Material::Emissive(Color::new(1., 1., 1.), 4.)));
+
+        let mut cam = Camera::new(1., 16);
+        cam.lookfrom = Point3::new(0., 3., 6.);
+        cam.lookat = Point3::new(0., 0., 0.);
+        cam.fov = 60.;
+        cam.samples_per_pixel = 16;
+        cam.max_bounces = 4;
+        cam.background = Color::zeros();
+        cam.lights = world.lights();
+
+        // both must tell which light a scattered ray hit, for its MIS weight
+        let list = cam.render_no_preview(&world);
+        let bvh = cam.render_no_preview(&BvhNode::new(world));
+        assert!(list == bvh, "Rendering a list and a BVH of the same scene must give the same image");
+    }
+
+    #[test]
+    fn bidirectional_matches_path_tracer() {
+        use crate::{material::Material, quad::Quad, sphere::Sphere, texture::Texture};
+
+        let render = |integrator: Box<dyn Integrator>| {
+            let mut world = HittableList::new();
+            let white: Material = Texture::new_solid_rgb(0.8, 0.8, 0.8).into();
+            let light = Material::Emissive(Color::new(1., 1., 1.), 4.);
+            world.add(Quad::new(Point3::new(-5., 0., -5.), Vec3::new(10., 0., 0.), Vec3::new(0., 0., 10.), white));
+            world.add(Quad::new(Point3::new(-2., 3., -1.), Vec3::new(1., 0., 0.), Vec3::new(0., 0., 1.), light.clone()));
+            world.add(Sphere::new(Point3::new(1., 1., 0.), 0.5, Material::Dielectric(1.5)));
+            world.add(Sphere::new(Point3::new(0., 2., -2.), 0.5, light));
+
+            let mut cam = Camera::new(1., 16);
+            cam.lookfrom = Point3::new(0., 3., 6.);
+            cam.lookat = Point3::new(0., 0., 0.);
+            cam.fov = 60.;
+            cam.samples_per_pixel = 256;
+            cam.max_bounces = 4;
+            cam.background = Color::zeros();
+            cam.integrator = integrator;
+            cam.lights = world.lights();
+            let world = BvhNode::new(world);
+            let image = cam.render_no_preview(&world);
+            image.pixels().iter().map(|p| p.x).sum::<f32>() / image.pixels().len() as f32
+        };
+
+        let (path, bidirectional) = (render(Box::new(PathTracer)), render(Box::new(Bidirectional)));
+        assert!((bidirectional / path - 1.).abs() < 0.05, "mean brightness {} bidirectional and {} path traced", bidirectional, path);
+    }
+
+    #[test]
+    fn bidirectional_same_image() {
+        use crate::{material::Material, quad::Quad, texture::Texture};
+
+        let mut world = HittableList::new();
+        let white: Material = Texture::new_solid_rgb(0.8, 0.8, 0.8).into();
+        let light = Material::Emissive(Color::new(1., 1., 1.), 4.);
+        world.add(Quad::new(Point3::new(-5., 0., -5.), Vec3::new(10., 0., 0.), Vec3::new(0., 0., 10.), white));
+        world.add(Quad::new(Point3::new(-1., 3., -1.), Vec3::new(1., 0., 0.), Vec3::new(0., 0., 1.), light));
+
+        let mut cam = Camera::new(1., 32);
+        cam.lookfrom = Point3::new(0., 3., 6.);
+        cam.lookat = Point3::new(0., 0., 0.);
+        cam.samples_per_pixel = 4;
+        cam.tile_size = 4;
+        cam.integrator = Box::new(Bidirectional);
+        cam.lights = world.lights();
+        let world = BvhNode::new(world);
+
+        // light paths splat onto pixels of other tiles
+        let mut render = |threads| {
+            let pool = ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+            pool.install(|| cam.render_no_preview(&world))
+        };
+        let single = render(1);
+        for _ in 0..4 {
+            assert!(single == render(4), "Bidirectional renders must not depend on the thread count");
+        }
+    }
+
+    #[test]
+    fn debug_integrators() {
+        use crate::sphere::Sphere;
+
+        let mut world = HittableList::new();
+        world.add(Sphere::new(Point3::zeros(), 1., Material::default()));
+        let world = BvhNode::new(world);
+        let mut cam = Camera::new(1., 16);
+        cam.lookfrom = Point3::new(0., 0., 5.);
+        cam.lookat = Point3::zeros();
+        cam.fov = 30.;
+        cam.samples_per_pixel = 4;
+
+        cam.integrator = Box::new(Normals);
+        let center = cam.render_no_preview(&world).get(8, 8);
+        assert!((center - Color::new(0.5, 0.5, 1.)).norm() < 0.1, "normal facing the camera is {}", center);
+
+        // nothing blocks a convex object from outside
+        cam.integrator = Box::new(AmbientOcclusion { radius: 10. });
+        assert_eq!(cam.render_no_preview(&world).get(8, 8), Color::new(1., 1., 1.));
+    }
+}
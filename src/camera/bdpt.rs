@@ -0,0 +1,415 @@
+use std::f32::consts::PI;
+
+use crate::color::Color;
+use crate::framebuffer::Splat;
+use crate::hittable::{Hittable, HitRecord};
+use crate::interval::Interval;
+use crate::light::{Light, Lights};
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::vec3::{Point3, Vec3};
+use super::{Camera, Projection};
+
+/// Bidirectional path tracing (Veach, 1997), in the formulation of pbrt.
+///
+/// Every camera sample traces one subpath from the camera and one from a
+/// randomly picked light, then joins every vertex of the one with every
+/// vertex of the other. Each of these strategies could have produced the
+/// resulting path, the balance heuristic weights them against each other.
+/// Joining light vertices directly to the lens lands on arbitrary pixels,
+/// those contributions are returned as splats.
+impl Camera {
+    /// Light arriving along the camera ray `r`, plus the splats light subpaths
+    /// leave on other pixels
+    pub(super) fn bidirectional<T: Hittable + Sync + ?Sized>(&self, r: &Ray, world: &T, sampler: &mut Sampler, splats: &mut Vec<Splat>) -> Color {
+        let max_depth = self.max_bounces as usize;
+        let time = r.time();
+        let mut radiance = Color::zeros();
+
+        // camera subpath, its first vertex is the point on the lens
+        let direction = r.direction().normalize();
+        let mut camera_path = vec![Vertex {
+            kind: Kind::Camera,
+            p: r.origin(),
+            normal: -self.w,
+            light: None,
+            beta: Color::repeat(1.),
+            pdf_fwd: 0.,
+            pdf_rev: 0.,
+            // nothing can connect to the lens of other projections
+            delta: !self.can_splat(),
+        }];
+        radiance += self.random_walk(world, *r, Color::repeat(1.),
+            self.lens_pdf(direction), max_depth + 2, sampler, &mut camera_path);
+
+        // light subpath
+        let mut light_path = vec![];
+        if let Some((light, choice_pdf)) = self.lights.choose(sampler) {
+            let (p, normal) = light.sample_point(time, sampler);
+            let pdf_pos = choice_pdf / light.area();
+            light_path.push(Vertex {
+                kind: Kind::Light,
+                p,
+                normal,
+                light: Some(light),
+                beta: Color::repeat(1. / pdf_pos),
+                pdf_fwd: pdf_pos,
+                pdf_rev: 0.,
+                delta: false,
+            });
+            let direction = light.sample_emission(normal, sampler);
+            let pdf_dir = light.emission_pdf(normal, direction);
+            if pdf_dir > 0. {
+                let beta = light.emitted(normal, direction) * normal.dot(&direction).abs() / (pdf_pos * pdf_dir);
+                self.random_walk(world, Ray::new(p, direction, time), beta, pdf_dir,
+                    max_depth + 1, sampler, &mut light_path);
+            }
+        }
+
+        let lens = match self.defocus_disk_u == Vec3::zeros() {
+            true => self.center,
+            false => self.defocus_disk_sample(self.center, sampler),
+        };
+        for t in 1..=camera_path.len() {
+            for s in 0..=light_path.len() {
+                if s + t < 2 || s + t - 2 > max_depth || (s == 1 && t == 1) { continue }
+                if t == 1 {
+                    if self.can_splat() {
+                        splats.extend(self.connect_to_lens(&light_path, &camera_path, s, lens, world, time, sampler));
+                    }
+                } else {
+                    radiance += self.connect(&light_path, &camera_path, s, t, world, time, sampler);
+                }
+            }
+        }
+        radiance
+    }
+
+    // private
+    /// Extends `path` with up to `max_vertices` vertices in total, starting
+    /// with `ray`, throughput `beta` and solid angle pdf `pdf_dir` of its
+    /// direction. Returns the background seen if the path leaves the scene.
+    #[allow(clippy::too_many_arguments)]
+    fn random_walk<'a, T: Hittable + Sync + ?Sized>(&'a self, world: &T, mut ray: Ray, mut beta: Color, mut pdf_dir: f32,
+        max_vertices: usize, sampler: &mut Sampler, path: &mut Vec<Vertex<'a>>) -> Color {
+        while path.len() < max_vertices {
+            let Some(mut rec) = world.sample_hit(&ray, Interval::new(0.001, f32::INFINITY), sampler) else {
+                // same depth limit as the path tracer
+                return match path.len() <= self.max_bounces as usize {
+                    true => beta.component_mul(&self.background),
+                    false => Color::zeros(),
+                };
+            };
+            let material = rec.material.get_or_insert_with(Material::default);
+            let light = match material.emitted() != Color::zeros() {
+                true => self.lights.find(&rec),
+                false => None,
+            };
+            let mut vertex = Vertex {
+                p: rec.p,
+                normal: if rec.front_face { rec.normal } else { -rec.normal },
+                kind: Kind::Surface(rec, ray),
+                light,
+                beta,
+                pdf_fwd: 0.,
+                pdf_rev: 0.,
+                delta: false,
+            };
+            let previous = path.last().expect("paths start at the camera or a light");
+            vertex.pdf_fwd = previous.to_area(pdf_dir, &vertex);
+            path.push(vertex);
+            if path.len() == max_vertices { break }
+
+            let vertex = path.last_mut().expect("just pushed");
+            let Kind::Surface(rec, r_in) = &vertex.kind else { unreachable!() };
+            let material = rec.material.as_ref().expect("set above");
+            let Some(bsdf) = material.sample(r_in, rec, sampler) else { break };
+            let pdf_rev = match bsdf.pdf {
+                Some(pdf) => {
+                    pdf_dir = pdf;
+                    material.pdf(&bsdf.ray, rec, -r_in.direction())
+                },
+                None => {
+                    vertex.delta = true;
+                    pdf_dir = 0.;
+                    0.
+                },
+            };
+            beta.component_mul_assign(&bsdf.weight);
+            ray = bsdf.ray;
+
+            // density of walking the other way, from this vertex to the previous one
+            let n = path.len();
+            path[n - 2].pdf_rev = path[n - 1].to_area(pdf_rev, &path[n - 2]);
+        }
+        Color::zeros()
+    }
+
+    /// Strategy with `s` light and `t` >= 2 camera vertices
+    #[allow(clippy::too_many_arguments)]
+    fn connect<T: Hittable + Sync + ?Sized>(&self, light_path: &[Vertex], camera_path: &[Vertex], s: usize, t: usize,
+        world: &T, time: f32, sampler: &mut Sampler) -> Color {
+        let pt = &camera_path[t - 1];
+        let contribution = if s == 0 {
+            // the camera subpath found a light by itself
+            let Kind::Surface(ref rec, _) = pt.kind else { return Color::zeros() };
+            let emitted = match pt.light {
+                Some(light) => light.emitted(pt.normal, (camera_path[t - 2].p - pt.p).normalize()),
+                // not one of `lights`, so no other strategy can find it
+                None => return pt.beta.component_mul(&rec.material.as_ref().expect("set by the walk").emitted()),
+            };
+            pt.beta.component_mul(&emitted)
+        } else {
+            let qs = &light_path[s - 1];
+            if !qs.is_connectible() || !pt.is_connectible() { return Color::zeros() }
+            let f = qs.beta.component_mul(&qs.f(pt)).component_mul(&pt.f(qs)).component_mul(&pt.beta);
+            if f == Color::zeros() { return Color::zeros() }
+            f * Self::geometry(qs, pt, world, time, sampler)
+        };
+        if contribution == Color::zeros() { return Color::zeros() }
+        contribution * self.mis_weight(light_path, camera_path, None, s, t)
+    }
+
+    /// Strategy with `s` light vertices and only the point `lens` on the
+    /// lens from the camera subpath: the pixel it lands on and its color
+    #[allow(clippy::too_many_arguments)]
+    fn connect_to_lens<T: Hittable + Sync + ?Sized>(&self, light_path: &[Vertex], camera_path: &[Vertex], s: usize,
+        lens: Point3, world: &T, time: f32, sampler: &mut Sampler) -> Option<Splat> {
+        let qs = &light_path[s - 1];
+        if !qs.is_connectible() { return None }
+        let (i, j) = self.raster(lens, qs.p)?;
+
+        let to_lens = lens - qs.p;
+        let distance = to_lens.norm();
+        let direction = to_lens / distance;
+        // solid angle pdf at `qs` of picking `lens`
+        let pdf = distance*distance / (direction.dot(&self.w) * self.lens_area());
+        let camera = Vertex {
+            kind: Kind::Camera,
+            p: lens,
+            normal: -self.w,
+            light: None,
+            beta: Color::repeat(self.importance(-direction) / pdf),
+            pdf_fwd: 0.,
+            pdf_rev: 0.,
+            delta: false,
+        };
+        let contribution = qs.beta.component_mul(&qs.f(&camera)).component_mul(&camera.beta)
+            * qs.cosine(direction);
+        if contribution == Color::zeros() { return None }
+
+        let shadow_ray = Ray::new(qs.p, direction, time);
+        let transmittance = world.transmittance(&shadow_ray, Interval::new(0.001, distance * (1. - 1e-4)), sampler);
+        if transmittance == 0. { return None }
+        let weight = self.mis_weight(light_path, camera_path, Some(&camera), s, 1);
+        Some((i, j, weight * transmittance * contribution))
+    }
+
+    /// Balance heuristic weight of the strategy with `s` light and `t` camera
+    /// vertices, against all other strategies that could make the same path.
+    /// `sampled` replaces the first camera vertex for `t` = 1.
+    fn mis_weight(&self, light_path: &[Vertex], camera_path: &[Vertex], sampled: Option<&Vertex>, s: usize, t: usize) -> f32 {
+        if s + t == 2 { return 1. }
+        let pt = match sampled {
+            Some(vertex) if t == 1 => vertex,
+            _ => &camera_path[t - 1],
+        };
+        let qs = (s > 0).then(|| &light_path[s - 1]);
+        let pt_minus = (t > 1).then(|| &camera_path[t - 2]);
+        let qs_minus = (s > 1).then(|| &light_path[s - 2]);
+
+        // forward and reverse densities of the vertices and whether they
+        // are specular, as seen from this strategy
+        let densities = |vertex: &Vertex| (vertex.pdf_fwd, vertex.pdf_rev, vertex.delta);
+        let mut camera: Vec<_> = camera_path[..t].iter().map(densities).collect();
+        let mut light: Vec<_> = light_path[..s].iter().map(densities).collect();
+
+        // the connected vertices are never specular and get their reverse
+        // densities from each other
+        camera[t - 1].2 = false;
+        camera[t - 1].1 = match qs {
+            Some(qs) => qs.pdf(self, pt),
+            None => pt.light_origin_pdf(&self.lights),
+        };
+        if let Some(pt_minus) = pt_minus {
+            camera[t - 2].1 = match qs {
+                Some(_) => pt.pdf(self, pt_minus),
+                None => pt.light_pdf(pt_minus),
+            };
+        }
+        if let Some(qs) = qs {
+            light[s - 1].2 = false;
+            light[s - 1].1 = pt.pdf(self, qs);
+        }
+        if let (Some(qs), Some(qs_minus)) = (qs, qs_minus) {
+            light[s - 2].1 = qs.pdf(self, qs_minus);
+        }
+
+        // specular vertices have no density, the strategies connecting them are skipped
+        let remap = |pdf: f32| if pdf != 0. { pdf } else { 1. };
+        let mut sum = 0.;
+        let mut ratio = 1.;
+        for i in (1..t).rev() {
+            ratio *= remap(camera[i].1) / remap(camera[i].0);
+            if !camera[i].2 && !camera[i - 1].2 { sum += ratio }
+        }
+        ratio = 1.;
+        for i in (0..s).rev() {
+            ratio *= remap(light[i].1) / remap(light[i].0);
+            if !(light[i].2 || i > 0 && light[i - 1].2) { sum += ratio }
+        }
+        1. / (1. + sum)
+    }
+
+    /// Geometry term between two vertices times the transmittance between
+    /// them, zero if they cannot see each other
+    fn geometry<T: Hittable + Sync + ?Sized>(a: &Vertex, b: &Vertex, world: &T, time: f32, sampler: &mut Sampler) -> f32 {
+        let d = b.p - a.p;
+        let distance = d.norm();
+        let direction = d / distance;
+        let shadow_ray = Ray::new(a.p, direction, time);
+        let transmittance = world.transmittance(&shadow_ray, Interval::new(0.001, distance * (1. - 1e-4)), sampler);
+        if transmittance == 0. { return 0. }
+        transmittance * a.cosine(direction) * b.cosine(direction) / (distance*distance)
+    }
+
+    /// Whether light subpaths can be joined to the lens, which needs a
+    /// perspective camera with a single eye
+    fn can_splat(&self) -> bool {
+        self.projection == Projection::Perspective && self.stereo.is_none()
+    }
+
+    /// Area of the image at distance one from the lens
+    fn image_area(&self) -> f32 {
+        let distance = (self.pixel00_loc - self.center).dot(&-self.w);
+        let width = self.pixel_delta_u.norm() * self.image_width as f32;
+        let height = self.pixel_delta_v.norm() * self.image_height as f32;
+        width * height / (distance*distance)
+    }
+
+    /// Area of the lens, one for a pinhole
+    fn lens_area(&self) -> f32 {
+        let radius = self.defocus_disk_u.norm();
+        if radius > 0. { PI * radius*radius } else { 1. }
+    }
+
+    /// Solid angle pdf of camera rays leaving the lens in the unit `direction`
+    fn lens_pdf(&self, direction: Vec3) -> f32 {
+        let cosine = direction.dot(&-self.w);
+        if cosine <= 0. { return 0. }
+        1. / (self.image_area() * cosine.powi(3))
+    }
+
+    /// Response of the whole image to light arriving at the lens from the
+    /// unit `direction`, the camera's counterpart of emitted radiance
+    fn importance(&self, direction: Vec3) -> f32 {
+        let cosine = direction.dot(&-self.w);
+        if cosine <= 0. { return 0. }
+        1. / (self.image_area() * self.lens_area() * cosine.powi(4))
+    }
+
+    /// Pixel whose camera rays from `lens` pass through `point`
+    fn raster(&self, lens: Point3, point: Point3) -> Option<(u32, u32)> {
+        let d = point - lens;
+        let along = d.dot(&-self.w);
+        if along <= 0. { return None }
+        let distance = (self.pixel00_loc - self.center).dot(&-self.w);
+        let on_image = lens + d * (distance / along);
+        let corner = self.pixel00_loc - 0.5*(self.pixel_delta_u + self.pixel_delta_v);
+        let x = (on_image - corner).dot(&self.pixel_delta_u) / self.pixel_delta_u.norm_squared();
+        let y = (on_image - corner).dot(&self.pixel_delta_v) / self.pixel_delta_v.norm_squared();
+        let inside = (0. ..self.image_width as f32).contains(&x) && (0. ..self.image_height as f32).contains(&y);
+        inside.then_some((x as u32, y as u32))
+    }
+}
+
+enum Kind {
+    Camera,
+    Light,
+    /// Hit of the ray that arrived at the vertex
+    Surface(HitRecord, Ray),
+}
+
+/// Point on a camera or light subpath
+struct Vertex<'a> {
+    kind: Kind,
+    p: Point3,
+    /// Outward normal on surfaces and lights, view direction for the camera
+    normal: Vec3,
+    /// Emitter the vertex lies on
+    light: Option<&'a Light>,
+    /// Throughput of the subpath up to and including this vertex
+    beta: Color,
+    /// Area density of reaching the vertex from its own subpath
+    pdf_fwd: f32,
+    /// Area density of reaching the vertex from the other end of the path
+    pdf_rev: f32,
+    /// Scatters into a single direction, so nothing can connect to it
+    delta: bool,
+}
+
+impl Vertex<'_> {
+    fn is_connectible(&self) -> bool {
+        match &self.kind {
+            Kind::Surface(rec, _) => !rec.material.as_ref().is_some_and(Material::is_specular),
+            _ => true,
+        }
+    }
+
+    fn is_on_surface(&self) -> bool {
+        match &self.kind {
+            Kind::Surface(rec, _) => !rec.material.as_ref().is_some_and(Material::is_volume),
+            Kind::Light => true,
+            Kind::Camera => false,
+        }
+    }
+
+    /// Cosine to the unit `direction` if the vertex lies on a surface, else one
+    fn cosine(&self, direction: Vec3) -> f32 {
+        if self.is_on_surface() { self.normal.dot(&direction).abs() } else { 1. }
+    }
+
+    /// Fraction of the light scattered or emitted towards `next`
+    fn f(&self, next: &Vertex) -> Color {
+        let direction = (next.p - self.p).normalize();
+        match &self.kind {
+            Kind::Surface(rec, r_in) => rec.material.as_ref()
+                .map_or(Color::zeros(), |material| material.eval(r_in, rec, direction)),
+            Kind::Light => self.light.map_or(Color::zeros(), |light| light.emitted(self.normal, direction)),
+            Kind::Camera => Color::zeros(),
+        }
+    }
+
+    /// Converts a solid angle density at this vertex into an area density at `next`
+    fn to_area(&self, pdf: f32, next: &Vertex) -> f32 {
+        let d = next.p - self.p;
+        let distance_squared = d.norm_squared();
+        if distance_squared == 0. { return 0. }
+        pdf * next.cosine(d / distance_squared.sqrt()) / distance_squared
+    }
+
+    /// Area density at `next` of continuing the path from this vertex
+    fn pdf(&self, camera: &Camera, next: &Vertex) -> f32 {
+        let direction = (next.p - self.p).normalize();
+        let pdf = match &self.kind {
+            Kind::Camera => camera.lens_pdf(direction),
+            Kind::Light => return self.light_pdf(next),
+            Kind::Surface(rec, r_in) => rec.material.as_ref()
+                .map_or(0., |material| material.pdf(r_in, rec, direction)),
+        };
+        self.to_area(pdf, next)
+    }
+
+    /// Area density at `next` of a light subpath leaving this vertex on an emitter
+    fn light_pdf(&self, next: &Vertex) -> f32 {
+        let Some(light) = self.light else { return 0. };
+        let direction = (next.p - self.p).normalize();
+        self.to_area(light.emission_pdf(self.normal, direction), next)
+    }
+
+    /// Area density of a light subpath starting at this vertex on an emitter
+    fn light_origin_pdf(&self, lights: &Lights) -> f32 {
+        self.light.map_or(0., |light| 1. / (lights.len() as f32 * light.area()))
+    }
+}
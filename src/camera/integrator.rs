@@ -0,0 +1,203 @@
+use std::fmt::Debug;
+
+use crate::color::Color;
+use crate::framebuffer::Splat;
+use crate::hittable::Hittable;
+use crate::interval::Interval;
+use crate::material::Material;
+use crate::pdf::{Onb, Pdf};
+use crate::photon::PhotonMapping;
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+
+use super::Camera;
+
+/// Light transport algorithm that finds the light arriving along camera
+/// rays, see [`Camera::integrator`]. Other crates can add their own:
+///
+/// ```
+/// use raytracing::{Camera, HittableList, Hittable, Interval};
+/// use raytracing::camera::Integrator;
+/// use raytracing::color::Color;
+/// use raytracing::framebuffer::Splat;
+/// use raytracing::ray::Ray;
+/// use raytracing::sampler::Sampler;
+///
+/// /// White wherever the camera sees something
+/// #[derive(Debug)]
+/// struct Coverage;
+///
+/// impl Integrator for Coverage {
+///     fn radiance(&self, _camera: &Camera, r: &Ray, world: &(dyn Hittable + Sync), _sampler: &mut Sampler,
+///         _splats: &mut Vec<Splat>) -> Color {
+///         match world.hit(r, Interval::new(0.001, f32::INFINITY)) {
+///             Some(_) => Color::new(1., 1., 1.),
+///             None => Color::zeros(),
+///         }
+///     }
+/// }
+///
+/// let mut cam = Camera::new(1., 16);
+/// let mut world = HittableList::new();
+/// world.two_spheres(&mut cam);
+/// cam.samples_per_pixel = 1;
+/// cam.integrator = Box::new(Coverage);
+/// let image = cam.render_no_preview(&world);
+/// assert!(image.pixels().contains(&Color::new(1., 1., 1.)));
+/// ```
+pub trait Integrator: Debug + Send + Sync {
+    /// Light arriving at the camera along `r`. Light found for other pixels,
+    /// such as by paths started at the lights, goes into `splats`.
+    fn radiance(&self, camera: &Camera, r: &Ray, world: &(dyn Hittable + Sync), sampler: &mut Sampler,
+        splats: &mut Vec<Splat>) -> Color;
+
+    /// True if `radiance` leaves splats, which rules out adaptive sampling
+    fn splats(&self) -> bool { false }
+
+    /// Settings of the photon map the camera builds before every pass, which
+    /// makes it always render progressively
+    fn photon_mapping(&self) -> Option<&PhotonMapping> { None }
+}
+
+impl Default for Box<dyn Integrator> {
+    fn default() -> Self { Box::new(PathTracer) }
+}
+
+/// Paths from the camera that sample the lights at every diffuse bounce
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PathTracer;
+
+impl Integrator for PathTracer {
+    fn radiance(&self, camera: &Camera, r: &Ray, world: &(dyn Hittable + Sync), sampler: &mut Sampler,
+        _splats: &mut Vec<Splat>) -> Color {
+        camera.ray_color(r, world, sampler, None)
+    }
+}
+
+/// Paths from the camera and from the lights joined in every possible way,
+/// which also finds caustics seen on diffuse surfaces. Light paths only
+/// reach the image through a perspective camera without stereo.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Bidirectional;
+
+impl Integrator for Bidirectional {
+    fn radiance(&self, camera: &Camera, r: &Ray, world: &(dyn Hittable + Sync), sampler: &mut Sampler,
+        splats: &mut Vec<Splat>) -> Color {
+        camera.bidirectional(r, world, sampler, splats)
+    }
+
+    fn splats(&self) -> bool { true }
+}
+
+/// The path tracer with caustics, light reaching diffuse surfaces through
+/// glass or off mirrors, gathered from photons shot from the lights
+impl Integrator for PhotonMapping {
+    fn radiance(&self, camera: &Camera, r: &Ray, world: &(dyn Hittable + Sync), sampler: &mut Sampler,
+        _splats: &mut Vec<Splat>) -> Color {
+        camera.ray_color(r, world, sampler, Some(&camera.photons))
+    }
+
+    fn photon_mapping(&self) -> Option<&PhotonMapping> { Some(self) }
+}
+
+/// Light straight from the emitters at the first diffuse surface, seen
+/// directly or through glass and in mirrors, like a Whitted ray tracer
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Whitted;
+
+impl Integrator for Whitted {
+    fn radiance(&self, camera: &Camera, r: &Ray, world: &(dyn Hittable + Sync), sampler: &mut Sampler,
+        _splats: &mut Vec<Splat>) -> Color {
+        let default = Material::default();
+        let mut throughput = Color::new(1., 1., 1.);
+        let mut ray = *r;
+
+        for _ in 0..camera.max_bounces {
+            let Some(hit) = world.sample_hit(&ray, Interval::new(0.001, f32::INFINITY), sampler) else {
+                return throughput.component_mul(&camera.background)
+            };
+            let material = hit.material.as_ref().unwrap_or(&default);
+            if !material.is_specular() {
+                // glowing media add their own light
+                let direct = camera.sample_light(&ray, &hit, material, world, sampler, false) + material.emitted();
+                return throughput.component_mul(&direct)
+            }
+            let emitted = material.emitted();
+            if emitted != Color::zeros() {
+                return throughput.component_mul(&emitted)
+            }
+
+            let Some(bsdf) = material.sample(&ray, &hit, sampler) else { break };
+            throughput.component_mul_assign(&bsdf.weight);
+            ray = bsdf.ray;
+        }
+        Color::zeros()
+    }
+}
+
+/// Outward normal of the first hit, mapped from [-1, 1] to [0, 1]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Normals;
+
+impl Integrator for Normals {
+    fn radiance(&self, _camera: &Camera, r: &Ray, world: &(dyn Hittable + Sync), _sampler: &mut Sampler,
+        _splats: &mut Vec<Splat>) -> Color {
+        let Some(hit) = world.hit(r, Interval::new(0.001, f32::INFINITY)) else { return Color::zeros() };
+        let outward = if hit.front_face { hit.normal } else { -hit.normal };
+        (outward + Color::new(1., 1., 1.)) / 2.
+    }
+}
+
+/// Texture coordinates of the first hit as red and green
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Uv;
+
+impl Integrator for Uv {
+    fn radiance(&self, _camera: &Camera, r: &Ray, world: &(dyn Hittable + Sync), _sampler: &mut Sampler,
+        _splats: &mut Vec<Splat>) -> Color {
+        let Some(hit) = world.hit(r, Interval::new(0.001, f32::INFINITY)) else { return Color::zeros() };
+        Color::new(hit.uv.0, hit.uv.1, 0.)
+    }
+}
+
+/// White where nothing within `radius` of the first hit blocks the sky
+/// above it, darker in creases and corners
+#[derive(Debug, Clone, Copy)]
+pub struct AmbientOcclusion {
+    pub radius: f32,
+}
+
+impl Integrator for AmbientOcclusion {
+    fn radiance(&self, _camera: &Camera, r: &Ray, world: &(dyn Hittable + Sync), sampler: &mut Sampler,
+        _splats: &mut Vec<Splat>) -> Color {
+        let Some(hit) = world.hit(r, Interval::new(0.001, f32::INFINITY)) else { return Color::zeros() };
+        // cosine-weighted, so that every unblocked direction counts the same
+        let direction = Pdf::Cosine(Onb::new(hit.normal)).generate(sampler);
+        let occluder = Ray::new(hit.p, direction, r.time());
+        match world.hit(&occluder, Interval::new(0.001, self.radius)) {
+            Some(_) => Color::zeros(),
+            None => Color::new(1., 1., 1.),
+        }
+    }
+}
+
+/// Heatmap of the bounding boxes and primitives a camera ray tests, from
+/// blue for none to red for `max` or more
+#[derive(Debug, Clone, Copy)]
+pub struct TraversalCost {
+    pub max: f32,
+}
+
+impl Default for TraversalCost {
+    fn default() -> Self { TraversalCost { max: 100. } }
+}
+
+impl Integrator for TraversalCost {
+    fn radiance(&self, _camera: &Camera, r: &Ray, world: &(dyn Hittable + Sync), _sampler: &mut Sampler,
+        _splats: &mut Vec<Splat>) -> Color {
+        let mut cost = 0;
+        world.hit_counting(r, Interval::new(0.001, f32::INFINITY), &mut cost);
+        let x = (cost as f32 / self.max).min(1.);
+        Color::new((4.*x - 2.).clamp(0., 1.), (4.*x).min(4. - 4.*x).clamp(0., 1.), (2. - 4.*x).clamp(0., 1.))
+    }
+}
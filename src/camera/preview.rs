@@ -0,0 +1,206 @@
+use anyhow::{Result, anyhow};
+use sdl2::EventPump;
+use sdl2::{
+    event::Event,
+    Sdl,
+    keyboard::Keycode,
+    pixels::PixelFormatEnum,
+    rect::Rect,
+    render::{WindowCanvas, Texture},
+};
+use std::sync::{mpsc::{self, RecvTimeoutError}, atomic::{AtomicBool, Ordering}};
+use std::thread;
+use std::time::{Duration, Instant};
+use rayon::prelude::*;
+
+use crate::framebuffer::{Framebuffer, RenderedPixel, Splat};
+use crate::color::DisplayTransform;
+use crate::tile::Tile;
+use crate::{hittable::Hittable, color::Color};
+use super::Camera;
+
+impl Camera {
+    pub fn render_with_preview<T: Hittable+Sync>(&mut self, world: &T)
+        -> Result<Framebuffer> {
+            if self.integrator.photon_mapping().is_some() {
+                return self.render_progressive(world)
+            }
+            self.in_window(|cam, texture, canvas, event_pump|
+                cam.preview_render_loop(world, texture, canvas, event_pump))
+        }
+
+    /// Renders the whole image at one sample per pixel and keeps refining it
+    /// pass by pass, showing every pass in the preview window. Stops once
+    /// `samples_per_pixel` passes are done, the `time_budget` is used up or
+    /// the window is closed.
+    pub fn render_progressive<T: Hittable+Sync>(&mut self, world: &T)
+        -> Result<Framebuffer> {
+            self.in_window(|cam, texture, canvas, event_pump|
+                cam.progressive_render_loop(world, texture, canvas, event_pump))
+        }
+
+    // private
+    fn in_window<F>(&mut self, render: F) -> Result<Framebuffer>
+        where F: FnOnce(&mut Self, Texture<'_>, WindowCanvas, &mut EventPump) -> Result<Framebuffer> {
+
+            // init SDL
+            let (sdl_context, canvas) = self.setup_sdl()?;
+
+            let mut event_pump = sdl_context
+                .event_pump()
+                .map_err(|e| anyhow!(e))?;
+            let (width, height) = self.output_size();
+            let texture_creator = canvas.texture_creator();
+            let texture = texture_creator
+                .create_texture_streaming(PixelFormatEnum::RGB24, width, height)?;
+
+            // Measure time
+            eprintln!("Starting render");
+            let start = Instant::now();
+
+            let framebuffer = render(self, texture, canvas, &mut event_pump)?;
+
+            eprintln!("\r Done.                   ");
+            let duration = start.elapsed();
+            eprintln!("Render took {:.2?}", duration);
+
+            Ok(framebuffer)
+        }
+
+    fn preview_render_loop<T: Hittable+Sync>(&mut self, world: &T, mut texture: Texture<'_>, mut canvas: WindowCanvas, event_pump: &mut EventPump)
+        -> Result<Framebuffer> {
+        let aovs = self.denoiser_aovs(world);
+        self.update();
+        let mut framebuffer = self.framebuffer();
+
+        let tiles = self.tiles();
+        let mut remaining = tiles.len();
+        let cancelled = AtomicBool::new(false);
+        let (sender, receiver) = mpsc::channel::<(usize, Tile, Vec<RenderedPixel>, Vec<Splat>)>();
+        // added in tile order once all tiles are done, for the same image on any number of threads
+        let mut splats = vec![vec![]; tiles.len()];
+        let camera = &*self;
+
+        thread::scope(|s| -> Result<()> {
+            // Workers render tiles in parallel, this thread only updates the window
+            s.spawn(|| {
+                tiles.into_par_iter().enumerate().for_each_with(sender, |sender, (index, tile)| {
+                    if cancelled.load(Ordering::Relaxed) { return }
+                    let (colors, splats) = camera.render_tile(&tile, world);
+                    let _ = sender.send((index, tile, colors, splats));
+                });
+            });
+
+            'rendering: {
+                while remaining > 0 {
+                    match receiver.recv_timeout(Duration::from_millis(30)) {
+                        Ok((index, tile, colors, tile_splats)) => {
+                            remaining -= 1;
+                            splats[index] = tile_splats;
+                            eprint!{"\rTiles remaining: {} ", remaining};
+                            framebuffer.write_tile(&tile, &colors);
+                            Self::write_tile_to_texture(&mut texture, &tile, &colors, &camera.display)?;
+                            canvas.clear();
+                            canvas.copy(&texture, None, None).map_err(|e| anyhow!(e))?;
+                            canvas.present();
+                        },
+                        Err(RecvTimeoutError::Timeout) => {},
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    }
+                    if Self::poll_quit(event_pump) {
+                        cancelled.store(true, Ordering::Relaxed);
+                        eprintln!(); break 'rendering
+                    }
+                }
+                for tile_splats in &splats {
+                    camera.add_splats(tile_splats);
+                }
+                if camera.denoiser.is_some() || camera.integrator.splats() {
+                    let scale = 1. / camera.samples_per_pixel.max(1) as f32;
+                    framebuffer = Self::denoised(camera.denoiser,
+                        camera.with_light_image(framebuffer.clone(), scale), aovs.as_ref());
+                    Self::write_framebuffer_to_texture(&mut texture, &framebuffer)?;
+                    canvas.clear();
+                    canvas.copy(&texture, None, None).map_err(|e| anyhow!(e))?;
+                    canvas.present();
+                }
+                loop { if Self::poll_quit(event_pump) { break 'rendering }}
+            }
+            Ok(())
+        })?;
+
+        Ok(framebuffer)
+    }
+
+    fn progressive_render_loop<T: Hittable+Sync>(&mut self, world: &T, mut texture: Texture<'_>, mut canvas: WindowCanvas, event_pump: &mut EventPump)
+        -> Result<Framebuffer> {
+        let mut quit = false;
+        let denoiser = self.denoiser;
+        let framebuffer = self.progressive_passes(world, |image, aovs| {
+            let image = Self::denoised(denoiser, image.clone(), aovs);
+            Self::write_framebuffer_to_texture(&mut texture, &image)?;
+            canvas.clear();
+            canvas.copy(&texture, None, None).map_err(|e| anyhow!(e))?;
+            canvas.present();
+            quit = Self::poll_quit(event_pump);
+            Ok(!quit)
+        })?;
+
+        if !quit {
+            loop { if Self::poll_quit(event_pump) { break }}
+        }
+        Ok(framebuffer)
+    }
+
+    fn write_framebuffer_to_texture(texture: &mut Texture<'_>, framebuffer: &Framebuffer) -> Result<()> {
+        texture.with_lock(None, |buffer, pitch| {
+            for j in 0..framebuffer.height() {
+                for i in 0..framebuffer.width() {
+                    Self::write_to_buffer(i, j, buffer, pitch, framebuffer.get(i, j), &framebuffer.display());
+                }
+            }
+        })
+        .map_err(|e| anyhow!(e))
+    }
+
+    fn write_tile_to_texture(texture: &mut Texture<'_>, tile: &Tile, rendered: &[RenderedPixel], display: &DisplayTransform) -> Result<()> {
+        let rect = Rect::new(tile.x as i32, tile.y as i32, tile.width, tile.height);
+        texture.with_lock(Some(rect), |buffer, pitch| {
+            for ((i, j), (color, _)) in tile.pixels().zip(rendered) {
+                Self::write_to_buffer(i - tile.x, j - tile.y, buffer, pitch, *color, display);
+            }
+        })
+        .map_err(|e| anyhow!(e))
+    }
+
+    fn setup_sdl(&self) -> Result<(Sdl, WindowCanvas)> {
+        let sdl_context = sdl2::init()
+            .map_err(|e| anyhow!(e))?;
+        let video_subsystem = sdl_context.video()
+            .map_err(|e| anyhow!(e))?;
+        let (width, height) = self.output_size();
+
+        let window = video_subsystem
+            .window("Raytracer", width, height)
+            .build()?;
+        let canvas = window.into_canvas().build()?;
+        Ok((sdl_context, canvas))
+    }
+
+    fn poll_quit(event_pump: &mut EventPump) -> bool {
+        for event in event_pump.poll_iter(){
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown { keycode: Some(Keycode::Escape), .. }
+                | Event::KeyDown { keycode: Some(Keycode::Q), ..}
+                => return true,
+                _ => {}
+            }
+        }
+        false
+    }
+    fn write_to_buffer(i: u32, j: u32, buffer: &mut [u8], pitch: usize, color: Color, display: &DisplayTransform) {
+        let offset: usize = (j*pitch as u32 + i*3) as usize;
+        buffer[offset..offset + 3].copy_from_slice(&display.to_rgb8(color));
+    }
+}
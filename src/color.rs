@@ -3,18 +3,101 @@ use crate::vec3;
 
 pub type Color = vec3::Vec3;
 
-pub fn linear_to_gamma(color: Color) -> Color {
-    color.map(f32::sqrt)
+/// sRGB transfer function, from linear light to encoded values
+pub fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.003_130_8 {
+        12.92 * value
+    } else {
+        1.055 * value.powf(1. / 2.4) - 0.055
+    }
 }
 
-pub fn write_color(i: u32, j: u32, buf: &mut ImageBuffer<image::Rgb<u8>, Vec<u8>>, color: Color) {
-    let color = linear_to_gamma(color);
+/// Relative luminance of linear Rec. 709 RGB
+pub fn luminance(color: Color) -> f32 {
+    color.dot(&Color::new(0.2126, 0.7152, 0.0722))
+}
+
+/// Curve compressing scene values above 1 into the displayable range
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ToneMapping {
+    /// Cut off everything above 1
+    #[default]
+    Clamp,
+    /// `x / (1 + x)`, never reaches white
+    Reinhard,
+    /// John Hable's filmic curve from Uncharted 2
+    Hable,
+    /// Krzysztof Narkowicz's fit of the ACES reference rendering transform
+    Aces,
+}
+
+impl ToneMapping {
+    pub fn map(&self, color: Color) -> Color {
+        match self {
+            ToneMapping::Clamp => color,
+            ToneMapping::Reinhard => color.map(|x| x / (1. + x)),
+            ToneMapping::Hable => {
+                fn curve(x: f32) -> f32 {
+                    let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+                    (x*(a*x + c*b) + d*e) / (x*(a*x + b) + d*f) - e/f
+                }
+                let white_point = 11.2;
+                color.map(|x| curve(2. * x) / curve(white_point))
+            },
+            ToneMapping::Aces => color.map(|x| {
+                let x = 0.6 * x;
+                x*(2.51*x + 0.03) / (x*(2.43*x + 0.59) + 0.14)
+            }),
+        }
+    }
+}
+
+/// Turns linear scene colors into sRGB encoded display values
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct DisplayTransform {
+    /// Brightness change in stops before tone mapping
+    pub exposure: f32,
+    pub tone_mapping: ToneMapping,
+}
 
+impl DisplayTransform {
+    /// sRGB encoded color with all channels in [0, 1]
+    pub fn apply(&self, color: Color) -> Color {
+        let exposed = color * 2f32.powf(self.exposure);
+        self.tone_mapping.map(exposed)
+            .map(|x| linear_to_srgb(x.clamp(0., 1.)))
+    }
+
+    pub fn to_rgb8(&self, color: Color) -> [u8; 3] {
+        let color = self.apply(color);
+        [color.x, color.y, color.z].map(|x| (255. * x).round() as u8)
+    }
+}
+
+pub fn write_color(i: u32, j: u32, buf: &mut ImageBuffer<image::Rgb<u8>, Vec<u8>>, color: Color, display: &DisplayTransform) {
     let pixel = buf.get_pixel_mut(i, j);
-    *pixel = image::Rgb([
-                        (256.*color.x) as u8,
-                        (256.*color.y) as u8,
-                        (256.*color.z) as u8,
-    ]);
+    *pixel = image::Rgb(display.to_rgb8(color));
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_range() {
+        assert_eq!(linear_to_srgb(0.), 0.);
+        assert!((linear_to_srgb(1.) - 1.).abs() < 1e-6);
+        assert!((linear_to_srgb(0.5) - 0.735_357).abs() < 1e-5);
+
+        use ToneMapping::*;
+        for tone_mapping in [Clamp, Reinhard, Hable, Aces] {
+            let display = DisplayTransform { tone_mapping, ..Default::default() };
+            assert_eq!(display.to_rgb8(Color::zeros()), [0, 0, 0], "{:?}", tone_mapping);
+            let values: Vec<u8> = [0.1, 0.5, 2., 20., 1e6].iter()
+                .map(|&x| display.to_rgb8(Color::repeat(x))[0])
+                .collect();
+            assert!(values.windows(2).all(|w| w[0] <= w[1]),
+                "{:?} must not get darker for brighter input: {:?}", tone_mapping, values);
+        }
+    }
+}
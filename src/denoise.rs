@@ -0,0 +1,152 @@
+use rayon::prelude::*;
+
+use crate::aov::Aovs;
+use crate::color::{Color, luminance};
+use crate::framebuffer::Framebuffer;
+
+/// Edge-avoiding à-trous wavelet filter (Dammertz et al., 2010).
+///
+/// Blurs the image with a growing, sparse 5x5 kernel and weights every
+/// neighbour by how similar its color, normal, albedo and depth are, so
+/// that edges in the AOVs survive. Texture is kept by filtering the
+/// image divided by the albedo and multiplying it back afterwards.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Denoiser {
+    /// Number of filter passes, the kernel spans 2^(iterations+2) pixels
+    pub iterations: u32,
+    /// Tolerated color difference, halved every pass
+    pub color_sigma: f32,
+    /// Tolerated distance between normals
+    pub normal_sigma: f32,
+    /// Tolerated albedo difference
+    pub albedo_sigma: f32,
+    /// Tolerated depth difference relative to the depth of the pixel
+    pub depth_sigma: f32,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Denoiser {
+            iterations: 5,
+            color_sigma: 1.0,
+            normal_sigma: 0.3,
+            albedo_sigma: 0.1,
+            depth_sigma: 0.05,
+        }
+    }
+}
+
+const KERNEL: [f32; 5] = [1./16., 1./4., 3./8., 1./4., 1./16.];
+
+// keeps the division by the albedo finite on black surfaces
+const MIN_ALBEDO: f32 = 0.01;
+
+impl Denoiser {
+    /// Filtered copy of `image`, `aovs` must have the same size.
+    pub fn denoise(&self, image: &Framebuffer, aovs: &Aovs) -> Framebuffer {
+        let (width, height) = (image.width(), image.height());
+        let albedo = aovs.albedo.pixels();
+        let normal = aovs.normal.pixels();
+        let depth = aovs.depth.pixels();
+
+        let guide = |index: usize| albedo[index].map(|a| a.max(MIN_ALBEDO));
+        let mut irradiance: Vec<Color> = image.pixels().iter().enumerate()
+            .map(|(index, color)| color.component_div(&guide(index)))
+            .collect();
+
+        for iteration in 0..self.iterations {
+            let step = 1 << iteration;
+            let color_sigma = self.color_sigma / (1 << iteration) as f32;
+            let mut filtered = vec![Color::zeros(); irradiance.len()];
+            filtered.par_chunks_mut(width as usize).enumerate().for_each(|(j, row)| {
+                for (i, out) in row.iter_mut().enumerate() {
+                    let p = j * width as usize + i;
+                    let mut sum = Color::zeros();
+                    let mut total = 0.;
+                    for (ky, hy) in KERNEL.iter().enumerate() {
+                        let y = j as i64 + (ky as i64 - 2) * step;
+                        if y < 0 || y >= height as i64 { continue }
+                        for (kx, hx) in KERNEL.iter().enumerate() {
+                            let x = i as i64 + (kx as i64 - 2) * step;
+                            if x < 0 || x >= width as i64 { continue }
+                            let q = y as usize * width as usize + x as usize;
+
+                            let color_distance = (compress(irradiance[p]) - compress(irradiance[q])).norm_squared();
+                            let normal_distance = (normal[p] - normal[q]).norm_squared();
+                            let albedo_distance = (albedo[p] - albedo[q]).norm_squared();
+                            let depth_distance = (depth[p].x - depth[q].x).abs()
+                                / (depth[p].x * step as f32).max(f32::EPSILON);
+                            let weight = hx * hy * (
+                                - color_distance / (color_sigma * color_sigma)
+                                - normal_distance / (self.normal_sigma * self.normal_sigma)
+                                - albedo_distance / (self.albedo_sigma * self.albedo_sigma)
+                                - depth_distance / self.depth_sigma
+                            ).exp();
+
+                            sum += weight * irradiance[q];
+                            total += weight;
+                        }
+                    }
+                    // the center pixel always has a weight, so total > 0
+                    *out = sum / total;
+                }
+            });
+            irradiance = filtered;
+        }
+
+        let mut denoised = image.clone();
+        for j in 0..height {
+            for i in 0..width {
+                let index = (j * width + i) as usize;
+                denoised.set(i, j, irradiance[index].component_mul(&guide(index)));
+            }
+        }
+        denoised
+    }
+}
+
+/// Maps colors into [0, 1) so the color weight works the same for bright
+/// and dark areas
+fn compress(color: Color) -> Color {
+    color / (1. + luminance(color))
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+    use crate::random::SampleRng;
+    use super::*;
+
+    fn variance(image: &Framebuffer, columns: std::ops::Range<u32>) -> f32 {
+        let values: Vec<f32> = (0..image.height())
+            .flat_map(|j| columns.clone().map(move |i| (i, j)))
+            .map(|(i, j)| image.get(i, j).x)
+            .collect();
+        let mean = values.iter().sum::<f32>() / values.len() as f32;
+        values.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / values.len() as f32
+    }
+
+    #[test]
+    fn smooths_noise_keeps_edges() {
+        // two flat walls with different normals, left one darker, plus noise
+        let mut rng = SampleRng::seed_from_u64(1);
+        let mut image = Framebuffer::new(32, 32);
+        let mut aovs = Aovs::new(32, 32);
+        for j in 0..32 {
+            for i in 0..32 {
+                let (base, normal) = if i < 16 { (0.2, Color::x()) } else { (0.8, Color::y()) };
+                image.set(i, j, Color::repeat(base * rng.gen_range(0.5..1.5)));
+                aovs.albedo.set(i, j, Color::repeat(0.5));
+                aovs.normal.set(i, j, normal);
+                aovs.depth.set(i, j, Color::repeat(1.));
+            }
+        }
+
+        let denoised = Denoiser::default().denoise(&image, &aovs);
+        for columns in [0..16, 16..32] {
+            assert!(variance(&denoised, columns.clone()) < variance(&image, columns.clone()) / 10.);
+        }
+        assert!((denoised.get(15, 16).x - 0.2).abs() < 0.05, "edge must not be blurred");
+        assert!((denoised.get(16, 16).x - 0.8).abs() < 0.1, "edge must not be blurred");
+    }
+}
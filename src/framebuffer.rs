@@ -0,0 +1,220 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use anyhow::Result;
+use image::{ImageBuffer, ImageFormat, Rgb, Rgb32FImage, RgbImage};
+use image::codecs::hdr::HdrEncoder;
+
+use crate::color::{Color, DisplayTransform, write_color};
+use crate::tile::Tile;
+
+/// Linear RGB image as produced by the camera, one color per pixel.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Framebuffer {
+    width: u32,
+    height: u32,
+    pixels: Vec<Color>,
+    samples: Vec<u32>,
+    display: DisplayTransform,
+}
+
+/// Color of a rendered pixel and the number of samples it is averaged from
+pub(crate) type RenderedPixel = (Color, u32);
+
+/// Light a path from an emitter carries to the pixel (i, j) through the lens
+pub type Splat = (u32, u32, Color);
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32) -> Self {
+        let pixels = vec![Color::zeros(); (width * height) as usize];
+        let samples = vec![0; (width * height) as usize];
+        Framebuffer { width, height, pixels, samples, display: DisplayTransform::default() }
+    }
+
+    pub fn width(&self) -> u32 { self.width }
+    pub fn height(&self) -> u32 { self.height }
+
+    /// How the 8 bit formats and the preview show the linear colors
+    pub fn display(&self) -> DisplayTransform { self.display }
+
+    pub fn set_display(&mut self, display: DisplayTransform) {
+        self.display = display;
+    }
+
+    /// Pixels in row-major order, starting at the upper left corner
+    pub fn pixels(&self) -> &[Color] { &self.pixels }
+
+    pub fn get(&self, i: u32, j: u32) -> Color {
+        self.pixels[self.index(i, j)]
+    }
+
+    pub fn set(&mut self, i: u32, j: u32, color: Color) {
+        let index = self.index(i, j);
+        self.pixels[index] = color;
+    }
+
+    /// Number of samples taken for every pixel, in the same order as [`Framebuffer::pixels`]
+    pub fn sample_counts(&self) -> &[u32] { &self.samples }
+
+    /// Grayscale image of the sample counts, white where the most samples were taken
+    pub fn sample_count_map(&self) -> Framebuffer {
+        let max = self.samples.iter().copied().max().unwrap_or(0).max(1);
+        let pixels = self.samples.iter()
+            .map(|&n| Color::repeat(n as f32 / max as f32))
+            .collect();
+        Framebuffer {
+            pixels,
+            samples: self.samples.clone(),
+            display: DisplayTransform::default(),
+            ..*self
+        }
+    }
+
+    /// Copies a rendered tile, pixels given in the order of [`Tile::pixels`].
+    pub(crate) fn write_tile(&mut self, tile: &Tile, rendered: &[RenderedPixel]) {
+        for ((i, j), (color, samples)) in tile.pixels().zip(rendered) {
+            let index = self.index(i, j);
+            self.pixels[index] = *color;
+            self.samples[index] = *samples;
+        }
+    }
+
+    /// Adds a rendered tile onto the pixels and sample counts already stored.
+    pub(crate) fn accumulate_tile(&mut self, tile: &Tile, rendered: &[RenderedPixel]) {
+        for ((i, j), (color, samples)) in tile.pixels().zip(rendered) {
+            let index = self.index(i, j);
+            self.pixels[index] += color;
+            self.samples[index] += samples;
+        }
+    }
+
+    /// Copy of the image with every pixel multiplied by `factor`.
+    pub fn scaled(&self, factor: f32) -> Framebuffer {
+        let pixels = self.pixels.iter().map(|p| p * factor).collect();
+        Framebuffer { pixels, samples: self.samples.clone(), ..*self }
+    }
+
+    /// Quantises the image to 8 bit sRGB using the display transform.
+    pub fn to_rgb8(&self) -> RgbImage {
+        let mut imgbuf: RgbImage = ImageBuffer::new(self.width, self.height);
+        for j in 0..self.height {
+            for i in 0..self.width {
+                write_color(i, j, &mut imgbuf, self.get(i, j), &self.display);
+            }
+        }
+        imgbuf
+    }
+
+    /// Linear float copy of the image, without losing values above 1.
+    pub fn to_rgb32f(&self) -> Rgb32FImage {
+        let data = self.pixels.iter().flat_map(|p| [p.x, p.y, p.z]).collect();
+        ImageBuffer::from_raw(self.width, self.height, data)
+            .expect("buffer matches the image size")
+    }
+
+    /// Saves the image, guessing the format from the file extension.
+    /// OpenEXR (.exr), Radiance (.hdr) and PFM (.pfm) files keep the linear
+    /// float values, all other formats are 8 bit.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let is_pfm = path.extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("pfm"));
+        if is_pfm {
+            return self.save_pfm(path)
+        }
+        let format = ImageFormat::from_path(path)?;
+        self.save_with_format(path, format)
+    }
+
+    pub fn save_with_format<P: AsRef<Path>>(&self, path: P, format: ImageFormat) -> Result<()> {
+        match format {
+            ImageFormat::OpenExr => self.to_rgb32f().save_with_format(path, format)?,
+            ImageFormat::Hdr => {
+                let pixels: Vec<Rgb<f32>> = self.pixels.iter()
+                    .map(|p| Rgb([p.x, p.y, p.z]))
+                    .collect();
+                let file = BufWriter::new(File::create(path)?);
+                HdrEncoder::new(file)
+                    .encode(&pixels, self.width as usize, self.height as usize)?;
+            },
+            _ => self.to_rgb8().save_with_format(path, format)?,
+        }
+        Ok(())
+    }
+
+    /// Writes a little endian Portable Float Map, which stores rows bottom to top.
+    pub fn save_pfm<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        write!(file, "PF\n{} {}\n-1.0\n", self.width, self.height)?;
+        for row in self.pixels.chunks(self.width as usize).rev() {
+            for p in row {
+                for c in [p.x, p.y, p.z] {
+                    file.write_all(&c.to_le_bytes())?;
+                }
+            }
+        }
+        file.flush()?;
+        Ok(())
+    }
+
+    fn index(&self, i: u32, j: u32) -> usize {
+        (j * self.width + i) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use tempdir::TempDir;
+    use super::*;
+
+    #[test]
+    fn save_png() -> Result<()> {
+        let tmp_dir = TempDir::new("testing")?;
+        let file_path = tmp_dir.path().join("image.png");
+
+        let mut framebuffer = Framebuffer::new(4, 2);
+        framebuffer.set(3, 1, Color::new(1., 0., 0.25));
+        framebuffer.save(&file_path)?;
+
+        let image = image::open(&file_path)?.into_rgb8();
+        assert_eq!(image.dimensions(), (4, 2));
+        assert_eq!(image.get_pixel(3, 1).0, [255, 0, 137]);
+        assert_eq!(image.get_pixel(0, 0).0, [0, 0, 0]);
+
+        assert!(framebuffer.save(tmp_dir.path().join("image.unknown")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn save_hdr_formats() -> Result<()> {
+        let tmp_dir = TempDir::new("testing")?;
+        let mut framebuffer = Framebuffer::new(3, 2);
+        framebuffer.set(2, 0, Color::new(4., 0.5, 0.));
+
+        let file_path = tmp_dir.path().join("image.exr");
+        framebuffer.save(&file_path)?;
+        let image = image::open(&file_path)?.into_rgb32f();
+        assert_eq!(image.get_pixel(2, 0).0, [4., 0.5, 0.]);
+
+        // image::open would convert Radiance files to 8 bit
+        let file_path = tmp_dir.path().join("image.hdr");
+        framebuffer.save(&file_path)?;
+        let reader = std::io::BufReader::new(File::open(&file_path)?);
+        let pixels = image::codecs::hdr::HdrDecoder::new(reader)?.read_image_hdr()?;
+        let [r, g, b] = pixels[2].0;
+        assert!((r - 4.).abs() < 0.05 && (g - 0.5).abs() < 0.01 && b == 0.,
+            "Radiance file must keep values above 1, got {:?}", (r, g, b));
+
+        let file_path = tmp_dir.path().join("image.pfm");
+        framebuffer.save(&file_path)?;
+        let bytes = std::fs::read(&file_path)?;
+        let header = b"PF\n3 2\n-1.0\n";
+        assert_eq!(&bytes[..header.len()], header);
+        assert_eq!(bytes.len(), header.len() + 3*2*3*4);
+        // upper right pixel is in the last row of the file
+        let offset = header.len() + (3 + 2) * 12;
+        assert_eq!(bytes[offset..offset+4], 4f32.to_le_bytes());
+        Ok(())
+    }
+}
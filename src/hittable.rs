@@ -5,6 +5,9 @@ use crate::{
     material::Material,
     aabb::AABB, sphere::Sphere,
     quad::Quad, triangle::{Triangle, Mesh},
+    instance::Instance,
+    medium::{ConstantMedium, GridMedium},
+    sampler::Sampler,
 };
 
 pub struct HitRecord {
@@ -14,6 +17,10 @@ pub struct HitRecord {
     pub t: f32,
     pub uv: (f32, f32),
     pub front_face: bool,
+    /// Set by the BVH to the index of the primitive in the scene plus one
+    pub object_id: u32,
+    /// Set by meshes to the index of the triangle in the mesh plus one
+    pub triangle_id: u32,
 }
 
 #[derive(Clone)]
@@ -22,6 +29,9 @@ pub enum Primitive {
     Quad(Quad),
     Triangle(Triangle),
     Mesh(Mesh),
+    Instance(Instance),
+    Medium(ConstantMedium),
+    GridMedium(GridMedium),
 }
 
 impl HitRecord {
@@ -37,6 +47,8 @@ impl HitRecord {
             front_face,
             material,
             uv,
+            object_id: 0,
+            triangle_id: 0,
         }
     }
 
@@ -46,8 +58,30 @@ impl HitRecord {
 }
 
 pub trait Hittable {
+    /// Closest surface along `r` within `ray_t`. Media are not surfaces,
+    /// where light scatters in them is random, see `sample_hit`.
     fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord>;
     fn bounding_box(&self) -> AABB;
+
+    /// Same as `hit`, adding the number of bounding boxes and primitives
+    /// it tested to `cost`
+    fn hit_counting(&self, r: &Ray, ray_t: Interval, cost: &mut u32) -> Option<HitRecord> {
+        *cost += 1;
+        self.hit(r, ray_t)
+    }
+
+    /// Same as `hit`, but light may also scatter in media on the way, at
+    /// a point drawn from `sampler`
+    fn sample_hit(&self, r: &Ray, ray_t: Interval, _sampler: &mut Sampler) -> Option<HitRecord> {
+        self.hit(r, ray_t)
+    }
+
+    /// Fraction of the light that gets through along `r` within `ray_t`,
+    /// zero if a surface blocks it. Media may give a random estimate from
+    /// `sampler` that is right on average.
+    fn transmittance(&self, r: &Ray, ray_t: Interval, _sampler: &mut Sampler) -> f32 {
+        if self.hit(r, ray_t).is_some() { 0. } else { 1. }
+    }
 }
 
 use Primitive::*;
@@ -59,6 +93,9 @@ impl Hittable for Primitive {
             Quad(q) => q.hit(r, ray_t),
             Triangle(t) => t.hit(r, ray_t),
             Mesh(m) => m.hit(r, ray_t),
+            Instance(i) => i.hit(r, ray_t),
+            Medium(m) => m.hit(r, ray_t),
+            GridMedium(m) => m.hit(r, ray_t),
         }
     }
 
@@ -68,6 +105,39 @@ impl Hittable for Primitive {
             Quad(q) => q.bounding_box(),
             Triangle(t) => t.bounding_box(),
             Mesh(m) => m.bounding_box(),
+            Instance(i) => i.bounding_box(),
+            Medium(m) => m.bounding_box(),
+            GridMedium(m) => m.bounding_box(),
+        }
+    }
+
+    fn hit_counting(&self, r: &Ray, ray_t: Interval, cost: &mut u32) -> Option<HitRecord> {
+        match self {
+            Mesh(m) => m.hit_counting(r, ray_t, cost),
+            Instance(i) => i.hit_counting(r, ray_t, cost),
+            _ => {
+                *cost += 1;
+                self.hit(r, ray_t)
+            },
+        }
+    }
+
+    fn sample_hit(&self, r: &Ray, ray_t: Interval, sampler: &mut Sampler) -> Option<HitRecord> {
+        match self {
+            Instance(i) => i.sample_hit(r, ray_t, sampler),
+            Medium(m) => m.sample_hit(r, ray_t, sampler),
+            GridMedium(m) => m.sample_hit(r, ray_t, sampler),
+            _ => self.hit(r, ray_t),
+        }
+    }
+
+    fn transmittance(&self, r: &Ray, ray_t: Interval, sampler: &mut Sampler) -> f32 {
+        match self {
+            Mesh(m) => m.transmittance(r, ray_t, sampler),
+            Instance(i) => i.transmittance(r, ray_t, sampler),
+            Medium(m) => m.transmittance(r, ray_t, sampler),
+            GridMedium(m) => m.transmittance(r, ray_t, sampler),
+            _ => if self.hit(r, ray_t).is_some() { 0. } else { 1. },
         }
     }
 }
@@ -92,4 +162,18 @@ impl From<Mesh> for Primitive {
         Mesh(value)
     }
 }
-
+impl From<Instance> for Primitive {
+    fn from(value: Instance) -> Self {
+        Instance(value)
+    }
+}
+impl From<ConstantMedium> for Primitive {
+    fn from(value: ConstantMedium) -> Self {
+        Medium(value)
+    }
+}
+impl From<GridMedium> for Primitive {
+    fn from(value: GridMedium) -> Self {
+        GridMedium(value)
+    }
+}
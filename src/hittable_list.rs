@@ -1,4 +1,5 @@
-use rand::{random, Rng};
+use rand::{Rng, SeedableRng};
+use anyhow::{Result, bail};
 
 use crate::{
     camera::Camera,
@@ -10,7 +11,9 @@ use crate::{
     interval::Interval,
     color::Color,
     sphere::Sphere,
-    vec3::{Point3, Vec3},
+    vec3::{Point3, Vec3, random_vec},
+    random::SampleRng,
+    sampler::Sampler,
     material::Material::{
         self,
         Lambertian,
@@ -19,6 +22,9 @@ use crate::{
         Emissive,
     },
     aabb::AABB, quad::Quad, triangle::Mesh, texture::Texture,
+    instance::Instance,
+    light::Lights,
+    medium::ConstantMedium,
 };
 
 pub struct HittableList<T: Hittable> {
@@ -31,6 +37,11 @@ impl HittableList<Primitive> {
         HittableList { objects: vec!(), bbox: Default::default() }
     }
 
+    /// Emitters of the objects added so far, for sampling them directly
+    pub fn lights(&self) -> Lights {
+        Lights::new(self)
+    }
+
     pub fn add<T: Into<Primitive>>(&mut self, item: T) {
         let item = item.into();
         self.bbox = AABB::from_aabbs(&self.bbox, &item.bounding_box());
@@ -38,29 +49,83 @@ impl HittableList<Primitive> {
     }
 }
 
+impl Default for HittableList<Primitive> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Names accepted by [`HittableList::scene`].
+pub const SCENES: [&str; 9] = [
+    "random_spheres", "bouncing_spheres", "two_spheres", "earth", "quads",
+    "triangle_mesh", "bugatti", "cornell_box", "cornell_smoke",
+];
+
 impl HittableList<Primitive> {
+    /// Builds one of the predefined scenes by name and sets up the camera for it.
+    pub fn scene(&mut self, name: &str, cam: &mut Camera) -> Result<()> {
+        match name {
+            "random_spheres" => self.random_spheres(cam),
+            "bouncing_spheres" => self.bouncing_spheres(cam),
+            "two_spheres" => self.two_spheres(cam),
+            "earth" => self.earth(cam),
+            "quads" => self.quads(cam),
+            "triangle_mesh" => self.triangle_mesh(cam)?,
+            "bugatti" => self.bugatti(cam)?,
+            "cornell_box" => self.cornell_box(cam),
+            "cornell_smoke" => self.cornell_smoke(cam),
+            _ => bail!("Unknown scene: {}", name),
+        }
+        Ok(())
+    }
+
+    /// Adds the contents of a Wavefront .obj file, materials from its mtllib included.
+    pub fn obj_file(&mut self, path: &str) -> Result<()> {
+        self.add(Mesh::load(path)?);
+        Ok(())
+    }
+
     pub fn random_spheres(&mut self, cam: &mut Camera) {
+        self.sphere_field(cam, false);
+    }
+
+    /// `random_spheres` with the small diffuse spheres bouncing up and the
+    /// metal sphere sliding sideways while the shutter is open
+    pub fn bouncing_spheres(&mut self, cam: &mut Camera) {
+        self.sphere_field(cam, true);
+        cam.shutter_open = 0.;
+        cam.shutter_close = 1.;
+    }
+
+    fn sphere_field(&mut self, cam: &mut Camera, moving: bool) {
         let green = Color::new(0.2, 0.3, 0.1).into();
         let white = Color::new(0.9, 0.9, 0.9).into();
         let checkered = Texture::new_checkered(0.32, green, white);
         let ground_material = Material::Lambertian(checkered);
         self.add(Sphere::new(Point3::new(0., -1000., 0.), 1000., ground_material));
 
+        // fixed seed, so that Camera::seed changes the noise but not the scene
+        let mut rng = SampleRng::seed_from_u64(0);
         for a in -11..11 {
             for b in -11..11 {
-                let choose_mat: f32 = random();
-                let center =Point3::new(a as f32 + 0.9*random::<f32>(), 0.2,
-                    b as f32+0.9*random::<f32>());
+                let choose_mat: f32 = rng.gen();
+                let center =Point3::new(a as f32 + 0.9*rng.gen::<f32>(), 0.2,
+                    b as f32+0.9*rng.gen::<f32>());
 
                 if (center-Point3::new(4., 0.2, 0.)).norm() > 0.9 {
                     let sphere_material = if choose_mat < 0.8 {
                         // diffuse
-                        let albedo = Color::new_random().component_mul(&Color::new_random());
+                        let albedo = random_vec(&mut rng).component_mul(&random_vec(&mut rng));
+                        if moving {
+                            let center2 = center + Vec3::new(0., rng.gen_range(0.0..0.5), 0.);
+                            self.add(Sphere::new_moving(center, center2, 0.2, Lambertian(albedo.into())));
+                            continue;
+                        }
                         Lambertian(albedo.into())
                     } else if choose_mat < 0.95 {
                         // Metal
-                        let albedo = (Color::new_random()/2.0).add_scalar(0.5);
-                        let fuzz = rand::thread_rng().gen_range(0.0..0.5);
+                        let albedo = (random_vec(&mut rng)/2.0).add_scalar(0.5);
+                        let fuzz = rng.gen_range(0.0..0.5);
                         Metal(albedo, fuzz)
                     } else {
                         // glass
@@ -78,7 +143,12 @@ impl HittableList<Primitive> {
         self.add(Sphere::new(Point3::new(-4.,1.,0.), 1.0, mat2));
 
         let mat3 = Metal(Color::new(0.7, 0.6, 0.5), 0.0);
-        self.add(Sphere::new(Point3::new(4.,1.,0.), 1.0, mat3));
+        let sphere3 = Sphere::new(Point3::new(4.,1.,0.), 1.0, mat3);
+        if moving {
+            self.add(Instance::moving(sphere3, Vec3::zeros(), Vec3::new(0., 0., 0.3)));
+        } else {
+            self.add(sphere3);
+        }
 
         cam.fov = 20.;
         cam.lookfrom = Point3::new(13.0,2.0,3.);
@@ -114,13 +184,11 @@ impl HittableList<Primitive> {
         // Materials
         let left_red     = Lambertian(Color::new(1.0, 0.2, 0.2).into());
         let back_green   = Lambertian(Color::new(0.2, 1.0, 0.2).into());
-        let right_blue   = Lambertian(Color::new(0.2, 0.2, 1.0).into());
         let upper_orange = Lambertian(Color::new(1.0, 0.5, 0.0).into());
         let lower_teal   = Lambertian(Color::new(0.2, 0.8, 0.8).into());
 
         // Quads
         let y_4 = Vec3::new(0., 4., 0.);
-        let tilt = Vec3::new(-2., 4., 0.);
         let x_4 = Vec3::new(4., 0., 0.);
         let z_4 = Vec3::new(0., 0., 4.);
         let z_neg_4 = Vec3::new(0., 0., -4.);
@@ -128,7 +196,6 @@ impl HittableList<Primitive> {
         let (t1, t2, t3) = (Point3::new(-2., -2., 0.),
                 Point3::new(2.,-2.,0.), Point3::new(-2.,2.,0.));
         self.add(Mesh::new_triangle(t1, t2, t3, Some(back_green)));
-        // self.add(Triangle::new(Point3::new( 3., -2., 1.), z_4, y_4, right_blue));
         self.add(Quad::new(Point3::new(-2.,  3., 1.), x_4, z_4, upper_orange));
         self.add(Quad::new(Point3::new(-2., -3., 5.), x_4, z_neg_4, lower_teal));
 
@@ -136,25 +203,18 @@ impl HittableList<Primitive> {
         cam.lookfrom = Point3::new(0., 0., 9.);
         cam.lookat = Point3::new(0., 0., 0.);
     }
-    pub fn triangle_mesh(&mut self, cam: &mut Camera) {
-        let blue = Metal(Color::new(0.2, 0.4, 0.9), 0.2);
-        let emissive = Emissive(Color::new(0.7, 0.7, 0.7), 10.);
-        let mesh = Mesh::load("assets/teapot.obj").unwrap();
-
-        let gray = Texture::new_solid_rgb(0.7, 0.7, 0.7);
-        // let boundary = Mesh::load("assets/box.obj").unwrap()
-        //     .with_material(gray.into());
-
+    pub fn triangle_mesh(&mut self, cam: &mut Camera) -> Result<()> {
+        let mesh = Mesh::load("assets/teapot.obj")?;
         self.add(mesh);
-        // self.add(boundary);
 
         cam.fov = 80.0;
         cam.lookfrom = Point3::new(0., 3., 5.);
         cam.lookat = Point3::new(0., 1., 0.);
+        Ok(())
     }
-    pub fn bugatti(&mut self, cam: &mut Camera) {
-        let car = Mesh::load("assets/bugatti/bugatti.obj").unwrap();
-        let bg = Mesh::load("assets/bugatti/background.obj").unwrap();
+    pub fn bugatti(&mut self, cam: &mut Camera) -> Result<()> {
+        let car = Mesh::load("assets/bugatti/bugatti.obj")?;
+        let bg = Mesh::load("assets/bugatti/background.obj")?;
 
         self.add(car);
         self.add(bg);
@@ -162,6 +222,7 @@ impl HittableList<Primitive> {
         cam.fov = 60.0;
         cam.lookfrom = Point3::new(6., 3., 7.);
         cam.lookat = Point3::new(0., 1., 0.);
+        Ok(())
     }
     pub fn cornell_box(&mut self, cam: &mut Camera) {
         let red: Material = Texture::new_solid_rgb(0.65, 0.05, 0.05).into();
@@ -186,16 +247,27 @@ impl HittableList<Primitive> {
         cam.lookat = Point3::new(278., 278., 0.0);
         // cam.background = Color::new(0.0, 0.0, 0.0);
     }
+    pub fn cornell_smoke(&mut self, cam: &mut Camera) {
+        self.cornell_box(cam);
+        let tall = Mesh::cuboid(Point3::new(265., 0., 295.), Point3::new(430., 330., 460.), None);
+        let short = Mesh::cuboid(Point3::new(130., 0., 65.), Point3::new(295., 165., 230.), None);
+        self.add(ConstantMedium::new(tall, 0.01, Texture::new_solid_rgb(0., 0., 0.)));
+        self.add(ConstantMedium::new(short, 0.01, Texture::new_solid_rgb(1., 1., 1.)));
+        cam.background = Color::zeros();
+    }
 }
 
 impl<T: Hittable> Hittable for HittableList<T> {
+    /// Object ids count from 1 in the order of `objects`, as in a BVH
+    /// built from the list
     fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
         let mut closest_so_far = ray_t.max;
         let mut result = None;
 
-        for object in &self.objects {
-            if let Some(hit) = object.hit(r, Interval::new(ray_t.min, closest_so_far)) {
+        for (index, object) in self.objects.iter().enumerate() {
+            if let Some(mut hit) = object.hit(r, Interval::new(ray_t.min, closest_so_far)) {
                 closest_so_far = hit.t;
+                hit.object_id = index as u32 + 1;
                 result = Some(hit);
             }
         }
@@ -206,6 +278,45 @@ impl<T: Hittable> Hittable for HittableList<T> {
     fn bounding_box(&self) -> AABB {
         self.bbox
     }
+
+    fn hit_counting(&self, r: &Ray, ray_t: Interval, cost: &mut u32) -> Option<HitRecord> {
+        let mut closest_so_far = ray_t.max;
+        let mut result = None;
+
+        for (index, object) in self.objects.iter().enumerate() {
+            if let Some(mut hit) = object.hit_counting(r, Interval::new(ray_t.min, closest_so_far), cost) {
+                closest_so_far = hit.t;
+                hit.object_id = index as u32 + 1;
+                result = Some(hit);
+            }
+        }
+
+        result
+    }
+
+    fn sample_hit(&self, r: &Ray, ray_t: Interval, sampler: &mut Sampler) -> Option<HitRecord> {
+        let mut closest_so_far = ray_t.max;
+        let mut result = None;
+
+        for (index, object) in self.objects.iter().enumerate() {
+            if let Some(mut hit) = object.sample_hit(r, Interval::new(ray_t.min, closest_so_far), sampler) {
+                closest_so_far = hit.t;
+                hit.object_id = index as u32 + 1;
+                result = Some(hit);
+            }
+        }
+
+        result
+    }
+
+    fn transmittance(&self, r: &Ray, ray_t: Interval, sampler: &mut Sampler) -> f32 {
+        let mut transmittance = 1.;
+        for object in &self.objects {
+            transmittance *= object.transmittance(r, ray_t, sampler);
+            if transmittance == 0. { break }
+        }
+        transmittance
+    }
 }
 
 impl<T: Into<Primitive>> From<Vec<T>> for HittableList<Primitive> {
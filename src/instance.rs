@@ -0,0 +1,73 @@
+use std::sync::Arc;
+
+use crate::{
+    hittable::{Hittable, HitRecord, Primitive},
+    interval::Interval,
+    ray::Ray,
+    vec3::Vec3,
+    aabb::AABB,
+    sampler::Sampler,
+};
+
+/// Copy of a shared primitive, displaced by an offset that moves linearly
+/// from `offset0` at time 0 to `offset1` at time 1.
+#[derive(Clone)]
+pub struct Instance {
+    object: Arc<Primitive>,
+    offset0: Vec3,
+    offset1: Vec3,
+    bbox: AABB,
+}
+
+impl Instance {
+    pub fn translate<T: Into<Primitive>>(object: T, offset: Vec3) -> Self {
+        Self::moving(object, offset, offset)
+    }
+
+    pub fn moving<T: Into<Primitive>>(object: T, offset0: Vec3, offset1: Vec3) -> Self {
+        let object: Primitive = object.into();
+        let bbox = object.bounding_box();
+        let bbox = AABB::from_aabbs(&bbox.translate(offset0), &bbox.translate(offset1));
+
+        Instance { object: Arc::new(object), offset0, offset1, bbox }
+    }
+
+    fn offset(&self, time: f32) -> Vec3 {
+        self.offset0 + time*(self.offset1 - self.offset0)
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        self.hit_counting(r, ray_t, &mut 0)
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.bbox
+    }
+
+    fn hit_counting(&self, r: &Ray, ray_t: Interval, cost: &mut u32) -> Option<HitRecord> {
+        // move the ray into object space instead of moving the object
+        let offset = self.offset(r.time());
+        let offset_r = Ray::new(r.origin() - offset, r.direction(), r.time());
+
+        let mut rec = self.object.hit_counting(&offset_r, ray_t, cost)?;
+        rec.p += offset;
+        Some(rec)
+    }
+
+    fn sample_hit(&self, r: &Ray, ray_t: Interval, sampler: &mut Sampler) -> Option<HitRecord> {
+        let offset = self.offset(r.time());
+        let offset_r = Ray::new(r.origin() - offset, r.direction(), r.time());
+
+        let mut rec = self.object.sample_hit(&offset_r, ray_t, sampler)?;
+        rec.p += offset;
+        Some(rec)
+    }
+
+    fn transmittance(&self, r: &Ray, ray_t: Interval, sampler: &mut Sampler) -> f32 {
+        let offset = self.offset(r.time());
+        let offset_r = Ray::new(r.origin() - offset, r.direction(), r.time());
+        self.object.transmittance(&offset_r, ray_t, sampler)
+    }
+}
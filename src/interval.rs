@@ -1,8 +1,3 @@
-use std::f32::{INFINITY, NEG_INFINITY};
-
-const EMPTY: Interval = Interval { min: INFINITY, max: NEG_INFINITY };
-const UNIVERSE: Interval = Interval { min: NEG_INFINITY, max: INFINITY };
-
 #[derive(Copy, Clone)]
 pub struct Interval {
     pub min: f32,
@@ -45,7 +40,15 @@ impl Interval {
 
 impl Default for Interval {
     fn default() -> Self {
-        Self { min: INFINITY, max: NEG_INFINITY }
+        Self { min: f32::INFINITY, max: f32::NEG_INFINITY }
     }
 }
 
+
+impl std::ops::Add<f32> for Interval {
+    type Output = Interval;
+
+    fn add(self, displacement: f32) -> Interval {
+        Interval::new(self.min + displacement, self.max + displacement)
+    }
+}
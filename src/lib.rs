@@ -2,7 +2,12 @@ pub mod camera;
 pub mod bvh;
 pub mod ray;
 pub mod hittable_list;
-mod color;
+pub mod framebuffer;
+pub mod color;
+pub mod aov;
+pub mod denoise;
+pub mod light;
+pub mod pdf;
 mod sphere;
 mod hittable;
 mod interval;
@@ -10,14 +15,26 @@ mod material;
 mod aabb;
 mod quad;
 mod triangle;
+mod instance;
 mod vec3;
 mod texture;
 mod obj;
+mod tile;
+mod random;
+pub mod sampler;
+pub mod photon;
+pub mod medium;
+pub mod voxel;
 
+#[cfg(feature = "preview")]
 extern crate sdl2;
 
 // re-exports
 pub use camera::Camera;
 pub use hittable_list::HittableList;
 pub use bvh::BvhNode;
+pub use framebuffer::Framebuffer;
+pub use hittable::{Hittable, HitRecord};
+pub use interval::Interval;
+pub use vec3::{Vec3, Point3};
 
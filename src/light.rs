@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+use crate::{
+    color::Color,
+    hittable::{HitRecord, Primitive},
+    hittable_list::HittableList,
+    material::Material,
+    pdf::{Onb, Pdf},
+    quad::Quad,
+    ray::Ray,
+    sampler::Sampler,
+    sphere::Sphere,
+    triangle::Triangle,
+    vec3::{Point3, Vec3},
+};
+
+/// Emissive primitive that can be sampled directly
+#[derive(Clone)]
+pub(crate) enum Light {
+    Quad(Quad),
+    Sphere(Sphere),
+    Triangle(Triangle),
+}
+
+impl Light {
+    pub(crate) fn material(&self) -> &Material {
+        match self {
+            Light::Quad(q) => q.material(),
+            Light::Sphere(s) => s.material(),
+            Light::Triangle(t) => t.material(),
+        }
+    }
+
+    pub(crate) fn area(&self) -> f32 {
+        match self {
+            Light::Quad(q) => q.area(),
+            Light::Sphere(s) => s.area(),
+            Light::Triangle(t) => t.area(),
+        }
+    }
+
+    /// Uniformly distributed point on the light at `time` and its normal
+    pub(crate) fn sample_point(&self, time: f32, sampler: &mut Sampler) -> (Point3, Vec3) {
+        match self {
+            Light::Quad(q) => q.sample_point(sampler),
+            Light::Sphere(s) => s.sample_point(time, sampler),
+            Light::Triangle(t) => t.sample_point(sampler),
+        }
+    }
+
+    /// Radiance leaving a point with `normal` in the unit `direction`.
+    /// Quads shine from both sides, spheres and triangles only outwards.
+    pub(crate) fn emitted(&self, normal: Vec3, direction: Vec3) -> Color {
+        let cosine = normal.dot(&direction);
+        let visible = match self {
+            Light::Quad(_) => cosine != 0.,
+            _ => cosine > 0.,
+        };
+        if visible { self.material().emitted() } else { Color::zeros() }
+    }
+
+    /// Cosine distributed unit direction for light leaving a point with `normal`
+    pub(crate) fn sample_emission(&self, normal: Vec3, sampler: &mut Sampler) -> Vec3 {
+        let side = match self {
+            Light::Quad(_) if sampler.next_1d() < 0.5 => -normal,
+            _ => normal,
+        };
+        Pdf::Cosine(Onb::new(side)).generate(sampler)
+    }
+
+    /// Solid angle pdf of `sample_emission` for the unit `direction`
+    pub(crate) fn emission_pdf(&self, normal: Vec3, direction: Vec3) -> f32 {
+        let cosine = normal.dot(&direction);
+        match self {
+            Light::Quad(_) => cosine.abs() / (2.*PI),
+            _ => cosine.max(0.) / PI,
+        }
+    }
+}
+
+/// Direction towards a point on a light, see [`Lights::sample`]
+pub(crate) struct LightSample {
+    pub direction: Vec3,
+    pub distance: f32,
+    /// Solid angle pdf, including the choice of the light
+    pub pdf: f32,
+    pub emitted: Color,
+}
+
+/// Emitters of a scene, for sampling them directly.
+///
+/// Holds the emissive spheres, quads and triangles, including the triangles
+/// of meshes. Emitters inside instances are only found by scattered rays.
+#[derive(Clone, Default)]
+pub struct Lights {
+    lights: Vec<Light>,
+    // index into `lights` by `HitRecord::object_id` and `triangle_id`
+    by_id: HashMap<(u32, u32), usize>,
+}
+
+impl Lights {
+    pub fn new(list: &HittableList<Primitive>) -> Self {
+        let mut lights = Lights::default();
+        for (index, object) in list.objects.iter().enumerate() {
+            lights.collect(object, (index as u32 + 1, 0));
+        }
+        lights
+    }
+
+    pub fn len(&self) -> usize { self.lights.len() }
+    pub fn is_empty(&self) -> bool { self.lights.is_empty() }
+
+    fn collect(&mut self, object: &Primitive, id: (u32, u32)) {
+        let emissive = |material: &Material| material.emitted() != Color::zeros();
+        let light = match object {
+            Primitive::Quad(q) if emissive(q.material()) => Light::Quad(q.clone()),
+            Primitive::Sphere(s) if emissive(s.material()) => Light::Sphere(s.clone()),
+            Primitive::Triangle(t) if emissive(t.material()) => Light::Triangle(t.clone()),
+            Primitive::Mesh(m) => {
+                for (index, triangle) in m.triangles().into_iter().enumerate() {
+                    self.collect(triangle, (id.0, index as u32 + 1));
+                }
+                return
+            },
+            _ => return,
+        };
+        self.by_id.insert(id, self.lights.len());
+        self.lights.push(light);
+    }
+
+    /// Uniformly picked light and the probability of picking it
+    pub(crate) fn choose(&self, sampler: &mut Sampler) -> Option<(&Light, f32)> {
+        if self.lights.is_empty() { return None }
+        let count = self.lights.len();
+        let index = ((sampler.next_1d() * count as f32) as usize).min(count - 1);
+        Some((&self.lights[index], 1. / count as f32))
+    }
+
+    /// Picks a light uniformly and a direction towards it from `origin`
+    pub(crate) fn sample(&self, origin: Point3, time: f32, sampler: &mut Sampler) -> Option<LightSample> {
+        let (light, choice_pdf) = self.choose(sampler)?;
+        let (direction, distance, pdf) = match light {
+            Light::Quad(q) => q.sample_direction(origin, sampler),
+            Light::Sphere(s) => s.sample_direction(origin, time, sampler),
+            Light::Triangle(t) => t.sample_direction(origin, sampler),
+        }?;
+        Some(LightSample {
+            direction,
+            distance,
+            pdf: pdf * choice_pdf,
+            emitted: light.material().emitted(),
+        })
+    }
+
+    /// The light hit in `rec`, None if it is not one of these
+    pub(crate) fn find(&self, rec: &HitRecord) -> Option<&Light> {
+        self.by_id.get(&(rec.object_id, rec.triangle_id)).map(|&index| &self.lights[index])
+    }
+
+    /// Solid angle pdf with which `sample` would have chosen the direction of
+    /// `r`, which hit an emitter in `rec`
+    pub(crate) fn pdf(&self, r: &Ray, rec: &HitRecord) -> f32 {
+        let pdf = match self.find(rec) {
+            Some(Light::Sphere(s)) => s.direction_pdf(r),
+            Some(Light::Quad(q)) => q.direction_pdf(r, rec.t),
+            Some(Light::Triangle(t)) => t.direction_pdf(r, rec.t),
+            None => 0.,
+        };
+        pdf / self.lights.len().max(1) as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bvh::BvhNode;
+    use crate::hittable::Hittable;
+    use crate::interval::Interval;
+    use crate::triangle::Mesh;
+    use super::*;
+
+    #[test]
+    fn find_mesh_triangle() {
+        let glow = Material::Emissive(Color::repeat(1.), 1.);
+        let cube = Mesh::cuboid(Point3::new(-1., -1., -1.), Point3::new(1., 1., 1.), Some(glow));
+        let mut list = HittableList::new();
+        list.add(cube);
+        let lights = Lights::new(&list);
+        assert_eq!(lights.len(), 12);
+        let world = BvhNode::new(list);
+
+        let origin = Point3::new(0.3, 0.2, 5.);
+        let r = Ray::new(origin, Vec3::new(0., 0., -1.), 0.);
+        let rec = world.hit(&r, Interval::new(0.001, f32::INFINITY)).expect("aimed at the cube");
+        let Some(Light::Triangle(triangle)) = lights.find(&rec) else { panic!("the cube's triangles are lights") };
+        // the triangle that was hit, not another one of the cube
+        assert!(triangle.hit(&r, Interval::new(0.001, f32::INFINITY)).is_some_and(|hit| hit.t == rec.t));
+        let pdf = lights.pdf(&r, &rec);
+        assert!(pdf > 0. && pdf == triangle.direction_pdf(&r, rec.t) / 12., "{}", pdf);
+    }
+}
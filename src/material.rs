@@ -1,5 +1,9 @@
+use std::f32::consts::PI;
+
 use crate::{
     vec3::{Vec3, reflect, refract},
+    pdf::{Pdf, Onb},
+    sampler::Sampler,
     ray::Ray,
     hittable::HitRecord,
     color::Color,
@@ -12,41 +16,122 @@ pub enum Material {
     Metal(Color, f32),
     Dielectric(f32),
     Emissive(Color, f32),
+    /// Phase function of fog and smoke, scatters the same in all directions.
+    /// Fire also adds the given light where it scatters.
+    Isotropic(Texture, Color),
+}
+
+/// Direction chosen by [`Material::sample`]
+pub struct BsdfSample {
+    pub ray: Ray,
+    /// BSDF times cosine divided by the pdf, what the light coming back
+    /// along `ray` gets multiplied by
+    pub weight: Color,
+    /// Solid angle pdf of the direction, None for specular reflection and
+    /// refraction, which only ever pick one direction
+    pub pdf: Option<f32>,
 }
 
 impl Material {
-    pub fn scatter(&self, r_in: &Ray, rec: &HitRecord) -> (Color, Option<Ray>) {
+    /// Fraction of the light arriving from `direction` that the surface
+    /// scatters back along `r_in`, per unit solid angle. Zero for specular
+    /// materials, which scatter into single directions only.
+    pub fn eval(&self, _r_in: &Ray, rec: &HitRecord, direction: Vec3) -> Color {
+        match self {
+            Material::Lambertian(texture) if direction.dot(&rec.normal) > 0. =>
+                texture.value(rec.uv, rec.p) / PI,
+            Material::Isotropic(texture, _) => texture.value(rec.uv, rec.p) / (4.*PI),
+            _ => Color::zeros(),
+        }
+    }
+
+    /// Solid angle pdf with which `sample` picks `direction`
+    pub fn pdf(&self, _r_in: &Ray, rec: &HitRecord, direction: Vec3) -> f32 {
+        match self {
+            Material::Lambertian(_) => Pdf::Cosine(Onb::new(rec.normal)).value(direction),
+            Material::Isotropic(..) => Pdf::Uniform.value(direction),
+            _ => 0.,
+        }
+    }
+
+    /// Draws the direction the ray continues in, None if it is absorbed
+    pub fn sample(&self, r_in: &Ray, rec: &HitRecord, sampler: &mut Sampler) -> Option<BsdfSample> {
         use Material::*;
         match self {
-            Lambertian(texture) => Self::scatter_lambertian(texture, rec),
-            Metal(color, fuzz) => Self::scatter_metal(color, fuzz, r_in, rec),
-            Dielectric(ir) => Self::scatter_dielectric(ir, r_in, rec),
-            Emissive(color, brightness) =>
-                Self::scatter_emissive(color, brightness, r_in, rec),
+            Lambertian(texture) => Self::sample_lambertian(texture, r_in, rec, sampler),
+            Metal(color, fuzz) => Self::sample_metal(color, fuzz, r_in, rec, sampler),
+            Dielectric(ir) => Self::sample_dielectric(ir, r_in, rec, sampler),
+            Emissive(..) => None,
+            Isotropic(texture, _) => Self::sample_isotropic(texture, r_in, rec, sampler),
         }
     }
 
-    fn scatter_lambertian(albedo: &Texture, rec: &HitRecord) -> (Color, Option<Ray>) {
-        let mut scatter_direction = rec.normal + Vec3::new_random();
-        if scatter_direction.relative_eq(&Vec3::zeros(), 0.001, 0.1) {
-            scatter_direction = rec.normal;
+    /// True if `eval` and `pdf` are zero everywhere, so sampling lights is useless
+    pub fn is_specular(&self) -> bool {
+        !matches!(self, Material::Lambertian(_) | Material::Isotropic(..))
+    }
+
+    /// True for the phase functions of media, which scatter inside a
+    /// volume, so no cosine to a surface applies
+    pub fn is_volume(&self) -> bool {
+        matches!(self, Material::Isotropic(..))
+    }
+
+    /// Light given off by the surface, black for everything but `Emissive`
+    /// and glowing media
+    pub fn emitted(&self) -> Color {
+        match self {
+            Material::Emissive(color, brightness) => *brightness * color,
+            Material::Isotropic(_, emission) => *emission,
+            _ => Color::zeros(),
         }
-        let scattered = Ray::new(rec.p, scatter_direction);
-        (albedo.value(rec.uv, rec.p), Some(scattered))
     }
-    fn scatter_metal(albedo: &Color, fuzz: &f32, r_in: &Ray, rec: &HitRecord) -> (Color, Option<Ray>) {
-        let reflected = reflect(r_in.direction().normalize(), &rec.normal);
-        let scattered = Ray::new(rec.p, reflected + *fuzz*Vec3::new_random());
 
-        if scattered.direction().dot(&rec.normal) > 0.0 {
-            (*albedo, Some(scattered))
-        } else {
-            (*albedo, None)
+    /// Base color of the surface, as written to the albedo AOV
+    pub fn albedo(&self, rec: &HitRecord) -> Color {
+        use Material::*;
+        match self {
+            Lambertian(texture) => texture.value(rec.uv, rec.p),
+            Metal(color, _) => *color,
+            Dielectric(_) => Color::new(1., 1., 1.),
+            Emissive(color, _) => *color,
+            Isotropic(texture, _) => texture.value(rec.uv, rec.p),
         }
     }
 
-    fn scatter_dielectric(ir: &f32, r_in: &Ray, rec: &HitRecord) -> (Color, Option<Ray>) {
-        let attenuation = Color::new(1.0, 1.0, 1.0);
+    fn sample_lambertian(albedo: &Texture, r_in: &Ray, rec: &HitRecord, sampler: &mut Sampler) -> Option<BsdfSample> {
+        let pdf = Pdf::Cosine(Onb::new(rec.normal));
+        let direction = pdf.generate(sampler);
+        let pdf = pdf.value(direction);
+        if pdf <= 0. { return None }
+
+        // cosine / pi of the BSDF and pdf cancel out
+        Some(BsdfSample {
+            ray: Ray::new(rec.p, direction, r_in.time()),
+            weight: albedo.value(rec.uv, rec.p),
+            pdf: Some(pdf),
+        })
+    }
+
+    fn sample_isotropic(albedo: &Texture, r_in: &Ray, rec: &HitRecord, sampler: &mut Sampler) -> Option<BsdfSample> {
+        // phase function and pdf are both uniform and cancel out
+        Some(BsdfSample {
+            ray: Ray::new(rec.p, sampler.unit_vector(), r_in.time()),
+            weight: albedo.value(rec.uv, rec.p),
+            pdf: Some(1. / (4.*PI)),
+        })
+    }
+
+    fn sample_metal(albedo: &Color, fuzz: &f32, r_in: &Ray, rec: &HitRecord, sampler: &mut Sampler) -> Option<BsdfSample> {
+        let reflected = reflect(r_in.direction().normalize(), &rec.normal);
+        let direction = reflected + *fuzz*sampler.unit_vector();
+        // fuzzed below the surface
+        if direction.dot(&rec.normal) <= 0.0 { return None }
+
+        Some(BsdfSample { ray: Ray::new(rec.p, direction, r_in.time()), weight: *albedo, pdf: None })
+    }
+
+    fn sample_dielectric(ir: &f32, r_in: &Ray, rec: &HitRecord, sampler: &mut Sampler) -> Option<BsdfSample> {
         let refraction_ratio = if rec.front_face {1.0/ir} else {*ir};
 
         let unit_direction = r_in.direction().normalize();
@@ -54,15 +139,16 @@ impl Material {
         let sin_theta = (1.0 - cos_theta*cos_theta).sqrt();
 
         let cannot_refract = refraction_ratio * sin_theta > 1.0;
-        let direction = match cannot_refract ||  Self::reflectance(cos_theta, refraction_ratio) > rand::random() {
+        let direction = match cannot_refract ||  Self::reflectance(cos_theta, refraction_ratio) > sampler.next_1d() {
             true => reflect(unit_direction, &rec.normal),
             false => refract(unit_direction, &rec.normal, refraction_ratio)
         };
 
-        (attenuation, Some(Ray::new(rec.p, direction)))
-    }
-    fn scatter_emissive(albedo: &Color, brightness: &f32, _r_in: &Ray, _rec: &HitRecord) -> (Color, Option<Ray>) {
-        (*brightness*albedo, None)
+        Some(BsdfSample {
+            ray: Ray::new(rec.p, direction, r_in.time()),
+            weight: Color::new(1.0, 1.0, 1.0),
+            pdf: None,
+        })
     }
 
     // Schlick's approximation
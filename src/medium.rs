@@ -0,0 +1,342 @@
+use std::sync::Arc;
+
+use crate::{
+    aabb::AABB,
+    color::Color,
+    hittable::{Hittable, HitRecord, Primitive},
+    interval::Interval,
+    material::Material,
+    ray::{Ray, Intersect},
+    sampler::Sampler,
+    sphere::Sphere,
+    texture::Texture,
+    triangle::Mesh,
+    vec3::{Point3, Vec3},
+    voxel::VoxelGrid,
+};
+
+/// Fog or smoke of the same density everywhere inside a closed convex
+/// boundary, such as a sphere or a box mesh
+#[derive(Clone)]
+pub struct ConstantMedium {
+    boundary: Arc<Primitive>,
+    neg_inv_density: f32,
+    phase_function: Material,
+}
+
+impl ConstantMedium {
+    /// `density` is the chance per scene unit that light scatters, `albedo`
+    /// the fraction of it that is not absorbed when it does
+    pub(crate) fn new<T: Into<Primitive>>(boundary: T, density: f32, albedo: Texture) -> Self {
+        ConstantMedium {
+            boundary: Arc::new(boundary.into()),
+            neg_inv_density: -1. / density,
+            phase_function: Material::Isotropic(albedo, Color::zeros()),
+        }
+    }
+
+    /// Medium filling the box between the opposite corners `a` and `b`
+    pub fn cuboid(a: Point3, b: Point3, density: f32, albedo: Color) -> Self {
+        Self::new(Mesh::cuboid(a, b, None), density, Texture::new_solid(albedo))
+    }
+
+    /// Medium filling a sphere
+    pub fn sphere(center: Point3, radius: f32, density: f32, albedo: Color) -> Self {
+        Self::new(Sphere::new(center, radius, Material::default()), density, Texture::new_solid(albedo))
+    }
+
+    /// Part of `ray_t` in which `r` is inside the boundary
+    fn inside(&self, r: &Ray, ray_t: Interval) -> Option<Interval> {
+        // front faces along the whole line, found from both directions so
+        // that meshes with back face culling work too
+        let everywhere = Interval::new(f32::NEG_INFINITY, f32::INFINITY);
+        let entry = self.boundary.hit(r, everywhere)?.t;
+        let reverse = Ray::new(r.origin(), -r.direction(), r.time());
+        let exit = -self.boundary.hit(&reverse, everywhere)?.t;
+
+        Some(Interval::new(entry.max(ray_t.min), exit.min(ray_t.max)))
+    }
+}
+
+impl Hittable for ConstantMedium {
+    fn hit(&self, _r: &Ray, _ray_t: Interval) -> Option<HitRecord> {
+        None
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.boundary.bounding_box()
+    }
+
+    fn sample_hit(&self, r: &Ray, ray_t: Interval, sampler: &mut Sampler) -> Option<HitRecord> {
+        scatter(r, self.inside(r, ray_t)?, self.neg_inv_density, &self.phase_function, sampler)
+    }
+
+    fn transmittance(&self, r: &Ray, ray_t: Interval, _sampler: &mut Sampler) -> f32 {
+        self.inside(r, ray_t).map_or(1., |inside| constant_transmittance(r, inside, self.neg_inv_density))
+    }
+}
+
+/// Smoke whose density varies over a voxel grid, such as the output of a
+/// fluid simulation, filling the box of the grid. Fire also glows from a
+/// grid of temperatures.
+///
+/// Rays find where they scatter by delta tracking and shadow rays their
+/// transmittance by ratio tracking: both step through the medium as if it
+/// were as dense as its densest voxel everywhere, and treat the missing
+/// density as particles that let the light pass (Novák et al., 2014).
+#[derive(Clone)]
+pub struct GridMedium {
+    density: Arc<VoxelGrid>,
+    scale: f32,
+    albedo: Color,
+    fire: Option<Fire>,
+}
+
+#[derive(Clone)]
+struct Fire {
+    temperature: Arc<VoxelGrid>,
+    brightness: f32,
+}
+
+impl GridMedium {
+    /// `scale` is the density, the chance per scene unit that light
+    /// scatters, where the grid's value is one, `albedo` the fraction of
+    /// the light that is not absorbed when it does
+    pub fn new(density: VoxelGrid, scale: f32, albedo: Color) -> Self {
+        GridMedium { density: Arc::new(density), scale, albedo, fire: None }
+    }
+
+    /// Lets the medium glow like a black body at the `temperature` in
+    /// kelvin, times `brightness`, as far as it absorbs light
+    pub fn with_fire(self, temperature: VoxelGrid, brightness: f32) -> Self {
+        let fire = Fire { temperature: Arc::new(temperature), brightness };
+        GridMedium { fire: Some(fire), ..self }
+    }
+
+    fn density(&self, p: Point3) -> f32 {
+        self.scale * self.density.value(p)
+    }
+
+    /// Light added where the medium scatters at `p`: the emission, weighted
+    /// by the chance that the particle there absorbs rather than scatters
+    fn emission(&self, p: Point3) -> Color {
+        match self.fire {
+            Some(ref fire) => fire.brightness * blackbody(fire.temperature.value(p))
+                .component_mul(&(Color::repeat(1.) - self.albedo)),
+            None => Color::zeros(),
+        }
+    }
+
+    /// Ray parameter of the next particle after `t`, real or not
+    fn step(&self, r: &Ray, t: f32, sampler: &mut Sampler) -> f32 {
+        let majorant = self.scale * self.density.max();
+        t - (1. - sampler.next_1d()).ln() / (majorant * r.direction().norm())
+    }
+}
+
+impl Hittable for GridMedium {
+    fn hit(&self, _r: &Ray, _ray_t: Interval) -> Option<HitRecord> {
+        None
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.density.bounding_box()
+    }
+
+    fn sample_hit(&self, r: &Ray, ray_t: Interval, sampler: &mut Sampler) -> Option<HitRecord> {
+        let inside = r.overlap(&self.density.bounding_box(), ray_t)?;
+        let majorant = self.scale * self.density.max();
+        if majorant <= 0. { return None }
+
+        let mut t = inside.min;
+        loop {
+            t = self.step(r, t, sampler);
+            if t >= inside.max { return None }
+            // real with the chance that the medium is that dense here
+            let p = r.at(t);
+            if sampler.next_1d() * majorant < self.density(p) {
+                let phase_function = Material::Isotropic(Texture::new_solid(self.albedo), self.emission(p));
+                return Some(HitRecord::new(p, Vec3::x(), t, r, Some(phase_function), (0., 0.)))
+            }
+        }
+    }
+
+    fn transmittance(&self, r: &Ray, ray_t: Interval, sampler: &mut Sampler) -> f32 {
+        let Some(inside) = r.overlap(&self.density.bounding_box(), ray_t) else { return 1. };
+        let majorant = self.scale * self.density.max();
+        if majorant <= 0. { return 1. }
+
+        let mut transmittance = 1.;
+        let mut t = inside.min;
+        loop {
+            t = self.step(r, t, sampler);
+            if t >= inside.max { break }
+            transmittance *= 1. - self.density(r.at(t)) / majorant;
+        }
+        transmittance
+    }
+}
+
+/// Haze of the same density everywhere in the bounding box of the scene,
+/// see [`Camera::atmosphere`](crate::camera::Camera::atmosphere)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Atmosphere {
+    /// Chance per scene unit that light scatters
+    pub density: f32,
+    /// Fraction of the scattered light that is not absorbed
+    pub albedo: Color,
+}
+
+impl Default for Atmosphere {
+    fn default() -> Self {
+        Atmosphere { density: 0.01, albedo: Color::new(1., 1., 1.) }
+    }
+}
+
+/// `world` filled with an atmosphere
+pub(crate) struct Hazy<'a, T: ?Sized> {
+    world: &'a T,
+    neg_inv_density: f32,
+    phase_function: Material,
+}
+
+impl<'a, T: Hittable + ?Sized> Hazy<'a, T> {
+    pub(crate) fn new(world: &'a T, atmosphere: &Atmosphere) -> Self {
+        Hazy {
+            world,
+            neg_inv_density: -1. / atmosphere.density,
+            phase_function: Material::Isotropic(Texture::new_solid(atmosphere.albedo), Color::zeros()),
+        }
+    }
+}
+
+impl<T: Hittable + ?Sized> Hittable for Hazy<'_, T> {
+    fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
+        self.world.hit(r, ray_t)
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.world.bounding_box()
+    }
+
+    fn hit_counting(&self, r: &Ray, ray_t: Interval, cost: &mut u32) -> Option<HitRecord> {
+        self.world.hit_counting(r, ray_t, cost)
+    }
+
+    fn sample_hit(&self, r: &Ray, ray_t: Interval, sampler: &mut Sampler) -> Option<HitRecord> {
+        let hit = self.world.sample_hit(r, ray_t, sampler);
+        let end = hit.as_ref().map_or(ray_t.max, |hit| hit.t);
+        let inside = r.overlap(&self.world.bounding_box(), Interval::new(ray_t.min, end));
+        inside.and_then(|inside| scatter(r, inside, self.neg_inv_density, &self.phase_function, sampler))
+            .or(hit)
+    }
+
+    fn transmittance(&self, r: &Ray, ray_t: Interval, sampler: &mut Sampler) -> f32 {
+        let world = self.world.transmittance(r, ray_t, sampler);
+        if world == 0. { return 0. }
+        let inside = r.overlap(&self.world.bounding_box(), ray_t);
+        world * inside.map_or(1., |inside| constant_transmittance(r, inside, self.neg_inv_density))
+    }
+}
+
+/// Point where light travelling along `r` through the medium between the
+/// ray parameters `inside` scatters, None if it passes through. The
+/// distance is drawn from `sampler`.
+fn scatter(r: &Ray, inside: Interval, neg_inv_density: f32, phase_function: &Material, sampler: &mut Sampler) -> Option<HitRecord> {
+    if inside.min >= inside.max { return None }
+
+    let length = r.direction().norm();
+    let distance = neg_inv_density * (1. - sampler.next_1d()).ln();
+    if distance > inside.size() * length { return None }
+
+    let t = inside.min + distance / length;
+    // the normal is arbitrary, media scatter the same in all directions
+    Some(HitRecord::new(r.at(t), Vec3::x(), t, r, Some(phase_function.clone()), (0., 0.)))
+}
+
+/// Fraction of the light travelling along `r` through the medium between
+/// the ray parameters `inside` that does not scatter
+fn constant_transmittance(r: &Ray, inside: Interval, neg_inv_density: f32) -> f32 {
+    if inside.min >= inside.max { return 1. }
+    (inside.size() * r.direction().norm() / neg_inv_density).exp()
+}
+
+/// Radiance of a black body at `kelvin` in W/(sr m² nm), at wavelengths of
+/// red, green and blue light
+fn blackbody(kelvin: f32) -> Color {
+    if kelvin <= 0. { return Color::zeros() }
+    let planck = |nm: f64| {
+        let lambda = nm * 1e-9;
+        // 2hc² and hc/k
+        let radiance = 1.191_042_97e-16 / lambda.powi(5) / ((1.438_777_3e-2 / (lambda * kelvin as f64)).exp() - 1.);
+        (radiance * 1e-9) as f32
+    };
+    Color::new(planck(630.), planck(532.), planck(465.))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bvh::BvhNode;
+    use crate::hittable_list::HittableList;
+    use crate::sampler::SamplerKind;
+    use crate::vec3::Point3;
+    use super::*;
+
+    #[test]
+    fn box_medium_transmittance() {
+        let medium = ConstantMedium::cuboid(Point3::new(0., 0., 0.), Point3::new(1., 1., 1.), 2., Color::repeat(1.));
+        let mut sampler = Sampler::new(SamplerKind::Independent, 0, (0, 0), 0, 1);
+
+        // rays starting outside and inside, crossing one unit of the medium
+        for start in [-1., 0.5] {
+            let passed = (0..10000)
+                .map(|k| Ray::new(Point3::new(0.5, k as f32 / 10000., start), Vec3::new(0., 0., 1.), 0.))
+                .filter(|r| medium.sample_hit(r, Interval::new(0.001, f32::INFINITY), &mut sampler).is_none())
+                .count();
+            let expected = (-2. * (1. - start.max(0.))).exp();
+            assert!((passed as f32 / 10000. - expected).abs() < 0.02, "{} passed from {}", passed, start);
+        }
+    }
+
+    #[test]
+    fn transmittance_through_bvh() {
+        let medium = ConstantMedium::sphere(Point3::zeros(), 1., 1., Color::repeat(1.));
+        let r = Ray::new(Point3::new(0., 0., -2.), Vec3::new(0., 0., 1.), 0.);
+        let ray_t = Interval::new(0.001, f32::INFINITY);
+        let expected = (-2f32).exp();
+        let mut sampler = Sampler::new(SamplerKind::Independent, 0, (0, 0), 0, 1);
+        assert!((medium.transmittance(&r, ray_t, &mut sampler) - expected).abs() < 1e-4);
+
+        // alone and next to surfaces the ray misses
+        let far = |x| Sphere::new(Point3::new(x, 0., 0.), 1., Material::default());
+        for objects in [vec![medium.clone().into()], vec![far(-5.).into(), medium.into(), far(5.).into()]] {
+            let world = BvhNode::new(HittableList::from(objects as Vec<Primitive>));
+            let transmittance = world.transmittance(&r, ray_t, &mut sampler);
+            assert!((transmittance - expected).abs() < 1e-4, "{}", transmittance);
+        }
+    }
+
+    #[test]
+    fn grid_medium_tracking() -> anyhow::Result<()> {
+        // clear in the half of the unit cube with x < 0.5, dense in the other
+        let grid = VoxelGrid::new([2, 1, 1], vec![0., 1.], Point3::zeros(), Point3::repeat(1.))?;
+        let medium = GridMedium::new(grid, 2., Color::repeat(0.5));
+        let ray_t = Interval::new(0.001, f32::INFINITY);
+        let mut sampler = Sampler::new(SamplerKind::Independent, 0, (0, 0), 0, 1);
+
+        for (x, expected) in [(0.25, 1.), (0.75, (-2f32).exp())] {
+            let rays = (0..10000).map(|k| Ray::new(Point3::new(x, k as f32 / 10000., -1.), Vec3::new(0., 0., 1.), 0.));
+            let passed = rays.clone().filter(|r| medium.sample_hit(r, ray_t, &mut sampler).is_none()).count() as f32 / 10000.;
+            let transmittance = rays.map(|r| medium.transmittance(&r, ray_t, &mut sampler)).sum::<f32>() / 10000.;
+            assert!((passed - expected).abs() < 0.02, "{} passed at x = {}", passed, x);
+            assert!((transmittance - expected).abs() < 0.02, "transmittance {} at x = {}", transmittance, x);
+        }
+
+        // fire glows red more than blue
+        let fire = medium.with_fire(VoxelGrid::new([1, 1, 1], vec![1500.], Point3::zeros(), Point3::repeat(1.))?, 1.);
+        let r = Ray::new(Point3::new(0.75, 0.5, -1.), Vec3::new(0., 0., 1.), 0.);
+        let glow = fire.sample_hit(&r, ray_t, &mut sampler).and_then(|hit| hit.material).map(|material| material.emitted());
+        assert!(glow.is_some_and(|glow| glow.x > glow.z && glow.z > 0.), "{:?}", glow);
+        Ok(())
+    }
+}
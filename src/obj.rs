@@ -1,7 +1,7 @@
-use std::{path::Path, fs::read_to_string, num::ParseFloatError, collections::HashMap};
+use std::{path::Path, fs::read_to_string, collections::HashMap};
 use crate::{
     vec3::Point3,
-    material::Material::{self}, color::Color, texture::Texture,
+    material::Material::{self}, color::Color,
 };
 use anyhow::{Result, bail, anyhow};
 
@@ -23,7 +23,7 @@ impl Obj {
         let mut materials: HashMap<String, Material> = HashMap::new();
         let mut current_mat: Option<Material> = None;
 
-        for line in read_to_string(&path)?.lines(){
+        for line in read_to_string(path.as_ref())?.lines(){
             // Ignore comments
             if line.starts_with('#') { continue }
             // load materials
@@ -32,20 +32,20 @@ impl Obj {
                     .ok_or(anyhow!("No mtllib name found!"))?;
                 let prefix = path.as_ref().parent()
                     .ok_or(anyhow!("No directory found"))?;
-                materials = MtlLoader::load(&prefix.join(filename))?;
+                materials = MtlLoader::load(prefix.join(filename))?;
             }
             // use material
-            if line.starts_with("usemtl ") {
-                current_mat = Some(materials.get(&line[7..])
+            if let Some(name) = line.strip_prefix("usemtl ") {
+                current_mat = Some(materials.get(name)
                     .ok_or(anyhow!("Invalid Material specified: {}", line))?.clone());
             }
             // read vertex
-            if line.starts_with("v ") {
-                let vertex: Point3 = parse_triplet(&line[2..])?;
+            if let Some(triplet) = line.strip_prefix("v ") {
+                let vertex: Point3 = parse_triplet(triplet)?;
                 vertices.push(vertex);
             }
-            if line.starts_with("f ") {
-                let nums = line[2..].split(' ')
+            if let Some(indices) = line.strip_prefix("f ") {
+                let nums = indices.split(' ')
                     .map(|x| {
                         x.split('/').next().and_then(|x| x.parse::<i32>()
                                                      .ok().map(|x| x-1))
@@ -92,17 +92,17 @@ impl MtlLoader {
             // Ignore comments
             if line.starts_with('#') { continue }
             // new material
-            if line.starts_with("newmtl ") {
+            if let Some(new_name) = line.strip_prefix("newmtl ") {
                 if let Some(ref name) = name { map.insert(name.to_string(), loader.make_material()); }
-                name = Some(line[7..].to_owned());
+                name = Some(new_name.to_owned());
                 loader = MtlLoader{..Default::default()};
             }
-            if line.starts_with("Kd ") {
-                let col: Color = parse_triplet(&line[3..])?;
+            if let Some(triplet) = line.strip_prefix("Kd ") {
+                let col: Color = parse_triplet(triplet)?;
                 loader.kd = col;
             }
-            if line.starts_with("Ns ") {
-                let ns: f32 = 1.-(200./line[3..].parse::<f32>()?);
+            if let Some(exponent) = line.strip_prefix("Ns ") {
+                let ns: f32 = 1.-(200./exponent.parse::<f32>()?);
                 loader.ns = ns;
             }
         }
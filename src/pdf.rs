@@ -0,0 +1,83 @@
+use std::f32::consts::PI;
+
+use crate::sampler::Sampler;
+use crate::vec3::Vec3;
+
+/// Orthonormal basis with `w` along a given direction
+#[derive(Debug, Clone, Copy)]
+pub struct Onb {
+    pub u: Vec3,
+    pub v: Vec3,
+    pub w: Vec3,
+}
+
+impl Onb {
+    pub fn new(direction: Vec3) -> Self {
+        let w = direction.normalize();
+        let a = if w.x.abs() > 0.9 { Vec3::y() } else { Vec3::x() };
+        let v = w.cross(&a).normalize();
+        let u = w.cross(&v);
+        Onb { u, v, w }
+    }
+
+    /// World space vector of coordinates given in this basis
+    pub fn transform(&self, local: Vec3) -> Vec3 {
+        local.x*self.u + local.y*self.v + local.z*self.w
+    }
+}
+
+/// Distribution of directions to importance sample, with its density
+/// over the solid angle.
+pub enum Pdf {
+    /// Proportional to the cosine to the `w` axis, zero below the surface
+    Cosine(Onb),
+    /// Same density for all directions
+    Uniform,
+}
+
+impl Pdf {
+    pub fn value(&self, direction: Vec3) -> f32 {
+        match self {
+            Pdf::Cosine(onb) => (direction.normalize().dot(&onb.w) / PI).max(0.),
+            Pdf::Uniform => 1. / (4.*PI),
+        }
+    }
+
+    /// Unit direction drawn from the distribution
+    pub fn generate(&self, sampler: &mut Sampler) -> Vec3 {
+        match self {
+            Pdf::Cosine(onb) => {
+                // project a uniform point on the disk up to the hemisphere
+                let (x, y) = sampler.unit_disk();
+                let z = (1. - x*x - y*y).max(0.).sqrt();
+                onb.transform(Vec3::new(x, y, z))
+            },
+            Pdf::Uniform => sampler.unit_vector(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sampler::SamplerKind;
+    use super::*;
+
+    #[test]
+    fn densities_integrate_to_one() {
+        let n = 4096;
+        for pdf in [Pdf::Cosine(Onb::new(Vec3::new(1., 2., 3.))), Pdf::Uniform] {
+            // Monte Carlo estimate of the integral over the sphere
+            let integral = (0..n)
+                .map(|index| {
+                    let mut sampler = Sampler::new(SamplerKind::Sobol, 0, (0, 0), index, n);
+                    pdf.value(sampler.unit_vector()) * 4. * PI
+                })
+                .sum::<f32>() / n as f32;
+            assert!((integral - 1.).abs() < 0.02, "density integrates to {}", integral);
+
+            let mut sampler = Sampler::new(SamplerKind::Independent, 0, (0, 0), 0, 1);
+            let direction = pdf.generate(&mut sampler);
+            assert!(pdf.value(direction) > 0., "generated directions must have a density");
+        }
+    }
+}
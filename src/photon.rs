@@ -0,0 +1,195 @@
+use std::f32::consts::PI;
+
+use crate::color::Color;
+use crate::hittable::{Hittable, HitRecord};
+use crate::interval::Interval;
+use crate::light::Lights;
+use crate::material::Material;
+use crate::ray::Ray;
+use crate::sampler::Sampler;
+use crate::vec3::{Point3, Vec3};
+
+/// [`Integrator`](crate::camera::Integrator) that adds caustics from
+/// progressive photon mapping to the path tracer, always rendering
+/// progressively.
+///
+/// Every pass emits new photons from the lights and keeps those that reach
+/// a diffuse surface through glass or off a mirror. Camera paths gather them
+/// at their diffuse hits, within a radius that shrinks from pass to pass so
+/// that the average of the passes converges (Knaus and Zwicker, 2011).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PhotonMapping {
+    /// Photons emitted per pass
+    pub photons: u32,
+    /// Gather radius of the first pass, a two hundredth of the diagonal of
+    /// the scene's bounding box if None
+    pub radius: Option<f32>,
+    /// Between 0 and 1, smaller values shrink the radius faster, which
+    /// trades noise for blur
+    pub alpha: f32,
+}
+
+impl Default for PhotonMapping {
+    fn default() -> Self {
+        PhotonMapping {
+            photons: 100_000,
+            radius: None,
+            alpha: 2. / 3.,
+        }
+    }
+}
+
+impl PhotonMapping {
+    /// Gather radius of pass `pass`, counting from 0
+    pub fn radius(&self, initial: f32, pass: u32) -> f32 {
+        let mut radius_squared = initial * initial;
+        for i in 1..=pass {
+            radius_squared *= (i as f32 + self.alpha) / (i as f32 + 1.);
+        }
+        radius_squared.sqrt()
+    }
+}
+
+/// Light arriving at a diffuse surface
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Photon {
+    position: Point3,
+    /// Unit direction the photon travelled in
+    direction: Vec3,
+    power: Color,
+    // splitting axis of the kd-tree node
+    axis: u8,
+}
+
+/// Follows one photon from a randomly picked light at `time` through glass
+/// and mirrors. Returns it where it first hits a diffuse surface after at
+/// least one such bounce, with its power for a single emitted photon.
+pub(crate) fn trace_photon<T: Hittable>(world: &T, lights: &Lights, time: f32, max_bounces: u32,
+    sampler: &mut Sampler) -> Option<Photon> {
+    let (light, choice_pdf) = lights.choose(sampler)?;
+    let (p, normal) = light.sample_point(time, sampler);
+    let direction = light.sample_emission(normal, sampler);
+    let pdf = choice_pdf / light.area() * light.emission_pdf(normal, direction);
+    if pdf <= 0. { return None }
+
+    let mut power = light.emitted(normal, direction) * normal.dot(&direction).abs() / pdf;
+    let mut ray = Ray::new(p, direction, time);
+    for bounce in 0..max_bounces {
+        let hit = world.sample_hit(&ray, Interval::new(0.001, f32::INFINITY), sampler)?;
+        let default = Material::default();
+        let material = hit.material.as_ref().unwrap_or(&default);
+        if !material.is_specular() {
+            // light reaching diffuse surfaces directly or scattering in
+            // media is left to the path tracer
+            return (bounce > 0 && !material.is_volume()).then(|| Photon {
+                position: hit.p,
+                direction: ray.direction().normalize(),
+                power,
+                axis: 0,
+            });
+        }
+        let bsdf = material.sample(&ray, &hit, sampler)?;
+        power.component_mul_assign(&bsdf.weight);
+        ray = bsdf.ray;
+    }
+    None
+}
+
+/// Photons of one pass in a balanced kd-tree, stored implicitly: every
+/// slice of the array has its node in the middle, the children left and
+/// right of it.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct PhotonMap {
+    photons: Vec<Photon>,
+    radius: f32,
+}
+
+impl PhotonMap {
+    /// Map gathering within `radius`, the power of the photons is divided
+    /// by the number of photons `emitted`
+    pub(crate) fn new(mut photons: Vec<Photon>, emitted: u32, radius: f32) -> Self {
+        for photon in &mut photons {
+            photon.power /= emitted.max(1) as f32;
+        }
+        Self::build(&mut photons);
+        PhotonMap { photons, radius }
+    }
+
+    /// Light reflected along `r` at a diffuse hit `rec`, estimated from the
+    /// photons around it
+    pub(crate) fn radiance(&self, r: &Ray, rec: &HitRecord, material: &Material) -> Color {
+        let mut sum = Color::zeros();
+        Self::find(&self.photons, rec.p, self.radius * self.radius, &mut |photon| {
+            sum += material.eval(r, rec, -photon.direction).component_mul(&photon.power);
+        });
+        sum / (PI * self.radius * self.radius)
+    }
+
+    fn build(photons: &mut [Photon]) {
+        if photons.len() <= 1 { return }
+        let (min, max) = photons.iter().fold(
+            (Point3::repeat(f32::INFINITY), Point3::repeat(f32::NEG_INFINITY)),
+            |(min, max), photon| (min.inf(&photon.position), max.sup(&photon.position)));
+        let axis = (max - min).imax();
+
+        let middle = photons.len() / 2;
+        photons.select_nth_unstable_by(middle, |a, b| a.position[axis].total_cmp(&b.position[axis]));
+        photons[middle].axis = axis as u8;
+        let (left, right) = photons.split_at_mut(middle);
+        Self::build(left);
+        Self::build(&mut right[1..]);
+    }
+
+    /// Calls `f` for every photon closer to `p` than the square root of `radius_squared`
+    fn find(photons: &[Photon], p: Point3, radius_squared: f32, f: &mut impl FnMut(&Photon)) {
+        if photons.is_empty() { return }
+        let middle = photons.len() / 2;
+        let node = &photons[middle];
+        let axis = node.axis as usize;
+        let offset = p[axis] - node.position[axis];
+        let (near, far) = if offset < 0. {
+            (&photons[..middle], &photons[middle + 1..])
+        } else {
+            (&photons[middle + 1..], &photons[..middle])
+        };
+
+        Self::find(near, p, radius_squared, f);
+        if offset * offset <= radius_squared {
+            if (node.position - p).norm_squared() <= radius_squared { f(node) }
+            Self::find(far, p, radius_squared, f);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sampler::SamplerKind;
+    use super::*;
+
+    #[test]
+    fn kd_tree_finds_all_neighbours() {
+        let mut sampler = Sampler::new(SamplerKind::Independent, 0, (0, 0), 0, 1);
+        let mut random_point = || Point3::new(sampler.next_1d(), sampler.next_1d(), sampler.next_1d());
+        let photons: Vec<Photon> = (0..1000)
+            .map(|_| Photon {
+                position: random_point(),
+                direction: Vec3::y(),
+                power: Color::repeat(1.),
+                axis: 0,
+            })
+            .collect();
+        let map = PhotonMap::new(photons.clone(), 1, 0.1);
+        assert_eq!(map.photons.len(), 1000);
+
+        for _ in 0..20 {
+            let p = random_point();
+            let expected = photons.iter().filter(|photon| (photon.position - p).norm_squared() <= 0.01).count();
+            let mut found = 0;
+            PhotonMap::find(&map.photons, p, 0.01, &mut |_| found += 1);
+            assert_eq!(found, expected);
+        }
+
+        let pass_radius = |pass| PhotonMapping::default().radius(1., pass);
+        assert!(pass_radius(0) == 1. && pass_radius(1) < 1. && pass_radius(100) < pass_radius(10));
+    }
+}
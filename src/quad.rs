@@ -4,6 +4,7 @@ use crate::{
     ray::Ray, vec3::{Point3, Vec3},
     material::Material,
     aabb::AABB,
+    sampler::Sampler,
 };
 
 #[derive(Clone)]
@@ -30,6 +31,38 @@ impl Quad {
         Quad { q, u, v, normal, d, mat, bbox, w }
     }
 
+    pub(crate) fn material(&self) -> &Material { &self.mat }
+
+    pub(crate) fn area(&self) -> f32 {
+        self.u.cross(&self.v).norm()
+    }
+
+    /// Uniformly distributed point on the quad and the normal there
+    pub(crate) fn sample_point(&self, sampler: &mut Sampler) -> (Point3, Vec3) {
+        let (a, b) = sampler.next_2d();
+        (self.q + a*self.u + b*self.v, self.normal)
+    }
+
+    /// Uniformly distributed point on the quad as seen from `origin`: unit
+    /// direction, distance and the solid angle pdf of the direction
+    pub(crate) fn sample_direction(&self, origin: Point3, sampler: &mut Sampler) -> Option<(Vec3, f32, f32)> {
+        let (a, b) = sampler.next_2d();
+        let to_point = self.q + a*self.u + b*self.v - origin;
+        let distance = to_point.norm();
+        let direction = to_point / distance;
+        let cosine = self.normal.dot(&direction).abs();
+        if cosine < 1e-6 { return None }
+
+        Some((direction, distance, distance*distance / (cosine * self.area())))
+    }
+
+    /// Solid angle pdf of `sample_direction` for a ray that hits the quad at `t`
+    pub(crate) fn direction_pdf(&self, r: &Ray, t: f32) -> f32 {
+        let distance = t * r.direction().norm();
+        let cosine = self.normal.dot(&r.direction().normalize()).abs();
+        distance*distance / (cosine * self.area())
+    }
+
     fn valid_uv_coords(u: f32, v: f32) -> bool {
         (0.0..1.0).contains(&u) && (0.0..1.0).contains(&v)
     }
@@ -0,0 +1,21 @@
+use rand::SeedableRng;
+use rand_pcg::Pcg32;
+
+/// Random number generator used for all sampling decisions while rendering.
+pub type SampleRng = Pcg32;
+
+/// Generator for one sample of one pixel. It only depends on its arguments,
+/// so a render is identical no matter how pixels are spread over threads.
+pub fn sample_rng(seed: u64, i: u32, j: u32, sample: u32) -> SampleRng {
+    let pixel = (j as u64) << 32 | i as u64;
+    let stream = mix(mix(seed ^ mix(pixel)) ^ sample as u64);
+    SampleRng::seed_from_u64(stream)
+}
+
+// SplitMix64 finalizer, spreads neighbouring inputs over the whole range
+fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e3779b97f4a7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
@@ -7,25 +7,36 @@ use crate::{
 pub struct Ray {
     orig: Point3,
     dir: Vec3,
+    tm: f32,
 }
 
 impl Ray {
-    pub fn new(orig: Point3, dir: Vec3) -> Self {
-        Ray{orig, dir}
+    pub fn new(orig: Point3, dir: Vec3, tm: f32) -> Self {
+        Ray{orig, dir, tm}
     }
     pub fn origin(&self) -> Point3 { self.orig }
     pub fn direction(&self) -> Vec3 { self.dir }
+    pub fn time(&self) -> f32 { self.tm }
     pub fn at(&self, t: f32) -> Point3 {
         self.orig + t*self.dir
     }
 }
 
+// bound on the relative rounding error of three f32 operations
+const GAMMA_3: f32 = 3. * f32::EPSILON / 2. / (1. - 3. * f32::EPSILON / 2.);
+
 pub trait Intersect<T> {
     fn intersects(self, other: &T, range: Interval) -> bool;
+    /// Part of `range` in which the ray is inside `other`
+    fn overlap(self, other: &T, range: Interval) -> Option<Interval>;
 }
 
 impl Intersect<AABB> for &Ray {
     fn intersects(self, other: &AABB, range: Interval) -> bool {
+        self.overlap(other, range).is_some()
+    }
+
+    fn overlap(self, other: &AABB, range: Interval) -> Option<Interval> {
         let mut ray_t = range;
         for a in 0..3 {
             let inv_d = 1./self.direction()[a];
@@ -35,13 +46,30 @@ impl Intersect<AABB> for &Ray {
             let mut t1 = (other.axis(a).max - orig) * inv_d;
 
             if inv_d < 0.0 { (t0, t1) = (t1, t0) }
+            // widen by a few rounding errors, thin boxes far away would
+            // otherwise be missed (pbrt 3rd edition, 3.9.2)
+            t1 += t1.abs() * 2. * GAMMA_3;
 
             if t0 > ray_t.min { ray_t.min = t0; }
             if t1 < ray_t.max { ray_t.max = t1; }
 
-            if ray_t.max <= ray_t.min { return false; }
+            if ray_t.max <= ray_t.min { return None; }
         }
-        true
+        Some(ray_t)
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thin_box_far_away() {
+        // padded to 0.0001 wide, which rounds away at this distance
+        let x = Interval::new(10000., 10000.0001);
+        let bbox = AABB::new(x, Interval::new(-1., 1.), Interval::new(-1., 1.));
+        let r = Ray::new(Point3::zeros(), Vec3::new(1., 0., 0.), 0.);
+        assert!(r.intersects(&bbox, Interval::new(0.001, f32::INFINITY)));
+    }
+}
@@ -0,0 +1,270 @@
+use rand::Rng;
+
+use crate::random::{SampleRng, sample_rng};
+use crate::vec3::Vec3;
+
+/// Strategy used to place the samples of a pixel.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SamplerKind {
+    /// Uniform random numbers for every dimension
+    #[default]
+    Independent,
+    /// Jittered samples, one per cell of a grid covering each pair of dimensions
+    Stratified,
+    /// Halton sequence, randomised per pixel by a Cranley-Patterson rotation
+    Halton,
+    /// Sobol (0,2)-sequence with random digit scrambling per pixel
+    Sobol,
+    /// R2 sequence offset by interleaved gradient noise, which spreads the
+    /// remaining error as blue noise over the image
+    BlueNoise,
+}
+
+/// Hands out the random numbers for one sample of one pixel.
+///
+/// Every call to `next_1d` or `next_2d` moves on to the next dimension(s), so
+/// the consumers have to ask for their numbers in the same order for every
+/// sample for the sequences to stay well distributed.
+pub struct Sampler {
+    kind: SamplerKind,
+    pixel: (u32, u32),
+    index: u32,
+    samples_per_pixel: u32,
+    dimension: u32,
+    scramble: u32,
+    rng: SampleRng,
+}
+
+const PRIMES: [u32; 32] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53,
+    59, 61, 67, 71, 73, 79, 83, 89, 97, 101, 103, 107, 109, 113, 127, 131,
+];
+
+// largest f32 below 1
+const ONE_MINUS_EPSILON: f32 = 1. - f32::EPSILON/2.;
+
+impl Sampler {
+    pub fn new(kind: SamplerKind, seed: u64, pixel: (u32, u32), index: u32, samples_per_pixel: u32) -> Self {
+        let rng = sample_rng(seed, pixel.0, pixel.1, index);
+        // same for every sample of the pixel, so the sequences stay intact
+        let scramble = sample_rng(seed, pixel.0, pixel.1, u32::MAX).gen();
+        let samples_per_pixel = samples_per_pixel.max(1);
+        Sampler { kind, pixel, index, samples_per_pixel, dimension: 0, scramble, rng }
+    }
+
+    pub fn next_1d(&mut self) -> f32 {
+        let dim = self.dimension;
+        self.dimension += 1;
+        let value = match self.kind {
+            SamplerKind::Independent => self.rng.gen(),
+            SamplerKind::Stratified => {
+                let n = self.samples_per_pixel;
+                if self.index >= n { return self.rng.gen() }
+                let stratum = permute(self.index, n, self.hash(dim));
+                (stratum as f32 + self.rng.gen::<f32>()) / n as f32
+            },
+            SamplerKind::Halton => self.halton(dim),
+            SamplerKind::Sobol => {
+                let index = self.shuffled_index(dim, self.samples_per_pixel.next_power_of_two());
+                to_unit(van_der_corput(index) ^ self.hash(dim))
+            },
+            SamplerKind::BlueNoise => {
+                // R1 sequence, the 1D analogue of R2
+                let r1 = 0.5 + self.shuffled_index(dim, self.samples_per_pixel) as f32 * 0.618_034;
+                (r1 + self.noise(dim)).fract()
+            },
+        };
+        value.min(ONE_MINUS_EPSILON)
+    }
+
+    pub fn next_2d(&mut self) -> (f32, f32) {
+        let dim = self.dimension;
+        let (x, y) = match self.kind {
+            SamplerKind::Independent => (self.rng.gen(), self.rng.gen()),
+            SamplerKind::Stratified => {
+                let n = self.samples_per_pixel;
+                if self.index >= n {
+                    (self.rng.gen(), self.rng.gen())
+                } else {
+                    let cols = (n as f32).sqrt().ceil() as u32;
+                    let rows = n.div_ceil(cols);
+                    // spread n samples over the cols*rows cells
+                    let cell = permute(self.index, n, self.hash(dim)) * (cols*rows) / n;
+                    let (sx, sy) = (cell % cols, cell / cols);
+                    ((sx as f32 + self.rng.gen::<f32>()) / cols as f32,
+                     (sy as f32 + self.rng.gen::<f32>()) / rows as f32)
+                }
+            },
+            SamplerKind::Halton => {
+                self.dimension += 2;
+                return (self.halton(dim), self.halton(dim+1))
+            },
+            SamplerKind::Sobol => {
+                let index = self.shuffled_index(dim, self.samples_per_pixel.next_power_of_two());
+                (to_unit(van_der_corput(index) ^ self.hash(dim)),
+                 to_unit(sobol_second(index) ^ self.hash(dim+1)))
+            },
+            SamplerKind::BlueNoise => {
+                // R2 sequence, based on the plastic number
+                let g = 1.324_718;
+                let index = self.shuffled_index(dim, self.samples_per_pixel) as f32;
+                let r2 = (0.5 + index / g, 0.5 + index / (g*g));
+                ((r2.0 + self.noise(dim)).fract(), (r2.1 + self.noise(dim+1)).fract())
+            },
+        };
+        self.dimension += 2;
+        (x.min(ONE_MINUS_EPSILON), y.min(ONE_MINUS_EPSILON))
+    }
+
+    /// Uniformly distributed direction
+    pub fn unit_vector(&mut self) -> Vec3 {
+        let (u1, u2) = self.next_2d();
+        let z = 1. - 2.*u1;
+        let r = (1. - z*z).max(0.).sqrt();
+        let phi = 2. * std::f32::consts::PI * u2;
+        Vec3::new(r * phi.cos(), r * phi.sin(), z)
+    }
+
+    /// Uniformly distributed point in the unit disk, using Shirley's
+    /// concentric mapping so that stratification carries over
+    pub fn unit_disk(&mut self) -> (f32, f32) {
+        let (u1, u2) = self.next_2d();
+        let (x, y) = (2.*u1 - 1., 2.*u2 - 1.);
+        if x == 0. && y == 0. { return (0., 0.) }
+
+        let quarter_pi = std::f32::consts::FRAC_PI_4;
+        let (r, theta) = if x.abs() > y.abs() {
+            (x, quarter_pi * (y / x))
+        } else {
+            (y, 2.*quarter_pi - quarter_pi * (x / y))
+        };
+        (r * theta.cos(), r * theta.sin())
+    }
+
+    fn halton(&mut self, dim: u32) -> f32 {
+        let Some(&base) = PRIMES.get(dim as usize) else { return self.rng.gen() };
+        let offset = to_unit(self.hash(dim));
+        (radical_inverse(self.index, base) + offset).fract()
+    }
+
+    /// The first two dimensions use the samples in order, later pairs shuffle
+    /// the first `n` of them so that the pairs are not correlated with each other.
+    fn shuffled_index(&self, dim: u32, n: u32) -> u32 {
+        if dim < 2 || self.index >= n {
+            self.index
+        } else {
+            permute(self.index, n, self.hash(dim/2))
+        }
+    }
+
+    /// Interleaved gradient noise of the pixel, shifted for every dimension
+    fn noise(&self, dim: u32) -> f32 {
+        let x = self.pixel.0 as f32 + 5.588_238 * dim as f32;
+        let y = self.pixel.1 as f32 + 5.588_238 * dim as f32;
+        (52.982_918 * (0.067_110_56 * x + 0.005_837_15 * y).fract()).fract()
+    }
+
+    fn hash(&self, dim: u32) -> u32 {
+        let mut h = self.scramble ^ dim.wrapping_mul(0x9e37_79b9);
+        h ^= h >> 16;
+        h = h.wrapping_mul(0x7feb_352d);
+        h ^= h >> 15;
+        h = h.wrapping_mul(0x846c_a68b);
+        h ^ (h >> 16)
+    }
+}
+
+fn to_unit(bits: u32) -> f32 {
+    (bits >> 8) as f32 / (1u32 << 24) as f32
+}
+
+fn radical_inverse(mut index: u32, base: u32) -> f32 {
+    let inv_base = 1. / base as f32;
+    let mut inv = inv_base;
+    let mut result = 0.;
+    while index > 0 {
+        result += (index % base) as f32 * inv;
+        index /= base;
+        inv *= inv_base;
+    }
+    result
+}
+
+fn van_der_corput(index: u32) -> u32 {
+    index.reverse_bits()
+}
+
+// Second dimension of the Sobol sequence
+fn sobol_second(mut index: u32) -> u32 {
+    let mut v: u32 = 1 << 31;
+    let mut result = 0;
+    while index != 0 {
+        if index & 1 == 1 { result ^= v; }
+        index >>= 1;
+        v ^= v >> 1;
+    }
+    result
+}
+
+/// Pseudo random permutation of 0..len (Kensler, "Correlated Multi-Jittered Sampling")
+fn permute(mut i: u32, len: u32, seed: u32) -> u32 {
+    let mut w = len - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= seed;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= seed >> 16;
+        i ^= (i & w) >> 4;
+        i ^= seed >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= seed >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | seed >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < len { break }
+    }
+    i.wrapping_add(seed) % len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixel_samples(kind: SamplerKind, n: u32) -> Vec<(f32, f32)> {
+        (0..n).map(|index| Sampler::new(kind, 3, (5, 9), index, n).next_2d()).collect()
+    }
+
+    #[test]
+    fn samples_in_unit_square() {
+        use SamplerKind::*;
+        for kind in [Independent, Stratified, Halton, Sobol, BlueNoise] {
+            for (x, y) in pixel_samples(kind, 64) {
+                assert!((0.0..1.0).contains(&x) && (0.0..1.0).contains(&y),
+                    "{:?} sample ({}, {}) outside of [0, 1)", kind, x, y);
+            }
+        }
+    }
+
+    #[test]
+    fn stratified_pixel_samples() {
+        for kind in [SamplerKind::Stratified, SamplerKind::Sobol] {
+            let mut cells = [0; 16];
+            for (x, y) in pixel_samples(kind, 16) {
+                cells[(y * 4.) as usize * 4 + (x * 4.) as usize] += 1;
+            }
+            assert_eq!(cells, [1; 16], "{:?} must put one sample into every cell", kind);
+        }
+    }
+}
@@ -7,11 +7,15 @@ use crate::{
     ray::Ray,
     material::Material,
     aabb::AABB,
+    sampler::Sampler,
+    pdf::Onb,
 };
 
 #[derive(Clone)]
 pub struct Sphere {
     center: Point3,
+    // displacement of the center between time 0 and time 1
+    motion: Vec3,
     radius: f32,
     mat: Material,
     bbox: AABB,
@@ -23,7 +27,67 @@ impl Sphere {
         let rvec = Vec3::new(radius, radius, radius);
         let bbox = AABB::from_points(center-rvec, center+rvec);
 
-        Sphere { center, radius, mat, bbox}
+        Sphere { center, motion: Vec3::zeros(), radius, mat, bbox}
+    }
+
+    /// Sphere moving linearly from `center0` at time 0 to `center1` at time 1
+    pub fn new_moving(center0: Point3, center1: Point3, radius: f32, mat: Material) -> Self {
+        let rvec = Vec3::new(radius, radius, radius);
+        let box0 = AABB::from_points(center0-rvec, center0+rvec);
+        let box1 = AABB::from_points(center1-rvec, center1+rvec);
+        let bbox = AABB::from_aabbs(&box0, &box1);
+
+        Sphere { center: center0, motion: center1 - center0, radius, mat, bbox }
+    }
+
+    fn center(&self, time: f32) -> Point3 {
+        self.center + time*self.motion
+    }
+
+    pub(crate) fn material(&self) -> &Material { &self.mat }
+
+    pub(crate) fn area(&self) -> f32 {
+        4.*PI*self.radius*self.radius
+    }
+
+    /// Uniformly distributed point on the sphere at `time` and the outward normal there
+    pub(crate) fn sample_point(&self, time: f32, sampler: &mut Sampler) -> (Point3, Vec3) {
+        let normal = sampler.unit_vector();
+        (self.center(time) + self.radius.abs()*normal, normal)
+    }
+
+    /// Uniformly distributed direction from `origin` into the cone the sphere
+    /// covers at `time`: unit direction, distance and the solid angle pdf
+    pub(crate) fn sample_direction(&self, origin: Point3, time: f32, sampler: &mut Sampler) -> Option<(Vec3, f32, f32)> {
+        let to_center = self.center(time) - origin;
+        let distance_squared = to_center.norm_squared();
+        // no cone from inside
+        if distance_squared <= self.radius*self.radius { return None }
+
+        let cos_theta_max = (1. - self.radius*self.radius / distance_squared).sqrt();
+        let (u1, u2) = sampler.next_2d();
+        let cos_theta = 1. - u1*(1. - cos_theta_max);
+        let sin_theta = (1. - cos_theta*cos_theta).max(0.).sqrt();
+        let phi = 2.*PI*u2;
+
+        let direction = Onb::new(to_center)
+            .transform(Vec3::new(phi.cos()*sin_theta, phi.sin()*sin_theta, cos_theta));
+
+        let r = Ray::new(origin, direction, time);
+        let distance = match self.hit(&r, Interval::new(0., f32::INFINITY)) {
+            Some(rec) => rec.t,
+            // grazing the silhouette
+            None => (distance_squared - self.radius*self.radius).sqrt(),
+        };
+        Some((direction, distance, 1. / (2.*PI*(1. - cos_theta_max))))
+    }
+
+    /// Solid angle pdf of `sample_direction` for a ray hitting the sphere
+    pub(crate) fn direction_pdf(&self, r: &Ray) -> f32 {
+        let distance_squared = (self.center(r.time()) - r.origin()).norm_squared();
+        if distance_squared <= self.radius*self.radius { return 0. }
+        let cos_theta_max = (1. - self.radius*self.radius / distance_squared).sqrt();
+        1. / (2.*PI*(1. - cos_theta_max))
     }
 
     pub fn uv(p: Point3) -> (f32, f32) {
@@ -38,7 +102,8 @@ impl Sphere {
 
 impl Hittable for Sphere {
     fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
-        let oc = r.origin() - self.center;
+        let center = self.center(r.time());
+        let oc = r.origin() - center;
         let a = r.direction().norm_squared();
         let half_b = oc.dot(&r.direction());
         let c = oc.norm_squared() - self.radius * self.radius;
@@ -58,7 +123,7 @@ impl Hittable for Sphere {
 
         let t = root;
         let p = r.at(root);
-        let outward_normal = (p - self.center)/self.radius;
+        let outward_normal = (p - center)/self.radius;
         let new_rec = HitRecord::new(p, outward_normal, t, r, Some(self.mat.clone()), Self::uv(outward_normal));
 
         Some(new_rec)
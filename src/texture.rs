@@ -1,5 +1,4 @@
 use std::sync::Arc;
-use anyhow::Result;
 use crate::{
     color::Color,
     vec3::Point3
@@ -12,6 +11,7 @@ pub struct CheckerTexture {
     odd: Texture,
 }
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Clone, PartialEq)]
 pub enum Texture {
     SolidColor(Color),
@@ -48,9 +48,9 @@ impl Texture {
         match *self {
             SolidColor(c) => c,
             CheckerTexture(ref c) => {
-                let x_int = (&c.scale * p.x).floor() as i32;
-                let y_int = (&c.scale * p.y).floor() as i32;
-                let z_int = (&c.scale * p.z).floor() as i32;
+                let x_int = (c.scale * p.x).floor() as i32;
+                let y_int = (c.scale * p.y).floor() as i32;
+                let z_int = (c.scale * p.z).floor() as i32;
                 let is_even: bool = (x_int + y_int + z_int) % 2 == 0;
 
                 if is_even { c.even.value(uv, p) } else { c.odd.value(uv, p) }
@@ -0,0 +1,52 @@
+/// Rectangular block of pixels that is rendered as one unit of work.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Tile {
+    /// Covers a `width` x `height` image with tiles of at most `size` x `size` pixels.
+    pub fn split(width: u32, height: u32, size: u32) -> Vec<Tile> {
+        let size = size.max(1);
+        let mut tiles = vec!();
+        for y in (0..height).step_by(size as usize) {
+            for x in (0..width).step_by(size as usize) {
+                tiles.push(Tile {
+                    x, y,
+                    width: size.min(width - x),
+                    height: size.min(height - y),
+                });
+            }
+        }
+        tiles
+    }
+
+    /// Image coordinates of all pixels in the tile, row by row.
+    pub fn pixels(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        (self.y..self.y+self.height)
+            .flat_map(move |j| (self.x..self.x+self.width).map(move |i| (i, j)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_covers_image() {
+        let tiles = Tile::split(10, 7, 4);
+        assert_eq!(tiles.len(), 6);
+        assert_eq!(tiles.last(), Some(&Tile { x: 8, y: 4, width: 2, height: 3 }));
+
+        let mut covered = vec![0; 70];
+        for tile in &tiles {
+            for (i, j) in tile.pixels() {
+                covered[(j*10 + i) as usize] += 1;
+            }
+        }
+        assert!(covered.iter().all(|&n| n == 1), "Every pixel must be in exactly one tile");
+    }
+}
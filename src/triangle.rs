@@ -7,7 +7,9 @@ use crate::{
     material::Material,
     aabb::AABB,
     vec3::{Vec3, Point3}, obj::{Obj, Face},
-    BvhNode, quad::Quad,
+    hittable::Primitive,
+    sampler::Sampler,
+    BvhNode,
 };
 
 const BACKFACE_CULLING: bool = true;
@@ -18,7 +20,7 @@ pub struct Mesh {
     bbox: AABB,
 }
 
-impl<'a> Mesh {
+impl Mesh {
     pub fn load(filepath: &str) -> Result<Mesh> {
         let obj = Obj::new(filepath)?;
 
@@ -48,28 +50,67 @@ impl<'a> Mesh {
                 },
             }
         }
-        let bvh = BvhNode::new(&mut triangles.into());
+        let bvh = BvhNode::new(triangles.into());
 
         Ok(Mesh { triangles: Arc::new(bvh), bbox })
     }
 
+    /// Closed box between the corners `a` and `b`, with its faces pointing out
+    pub fn cuboid(a: Point3, b: Point3, mat: Option<Material>) -> Self {
+        let (min, max) = (a.inf(&b), a.sup(&b));
+        let d = max - min;
+        let (dx, dy, dz) = (Vec3::new(d.x, 0., 0.), Vec3::new(0., d.y, 0.), Vec3::new(0., 0., d.z));
+        // corner and two edges of every face, in the order that makes the
+        // triangle normals point outwards
+        let faces = [
+            (min, dy, dx), (Point3::new(min.x, min.y, max.z), dx, dy),
+            (min, dz, dy), (Point3::new(max.x, min.y, min.z), dy, dz),
+            (min, dx, dz), (Point3::new(min.x, max.y, min.z), dz, dx),
+        ];
+        let mut triangles = vec![];
+        for (corner, u, v) in faces {
+            triangles.push(Triangle::new(corner, corner + u, corner + u + v, mat.clone()));
+            triangles.push(Triangle::new(corner, corner + u + v, corner + v, mat.clone()));
+        }
+        let bbox = AABB::from_points(min, max);
+        Mesh { triangles: Arc::new(BvhNode::new(triangles.into())), bbox }
+    }
+
     pub fn new_triangle(t1: Point3, t2: Point3, t3: Point3, mat: Option<Material>) -> Self{
         let tri = Triangle::new(t1, t2, t3, mat);
         let bbox = tri.bounding_box();
-        let triangles = Arc::new(BvhNode::new(&mut vec![tri].into()));
+        let triangles = Arc::new(BvhNode::new(vec![tri].into()));
 
         Mesh { triangles , bbox }
     }
 }
 
-impl<'a> Hittable for Mesh {
+impl Mesh {
+    /// Triangles in the order of their `HitRecord::triangle_id`
+    pub(crate) fn triangles(&self) -> Vec<&Primitive> {
+        self.triangles.primitives()
+    }
+}
+
+impl Hittable for Mesh {
     fn hit(&self, r: &Ray, ray_t: Interval) -> Option<HitRecord> {
-        self.triangles.hit(r, ray_t)
+        self.hit_counting(r, ray_t, &mut 0)
     }
 
     fn bounding_box(&self) -> AABB {
         self.bbox
     }
+
+    fn hit_counting(&self, r: &Ray, ray_t: Interval, cost: &mut u32) -> Option<HitRecord> {
+        let mut rec = self.triangles.hit_counting(r, ray_t, cost)?;
+        // the mesh's own BVH numbers its triangles
+        rec.triangle_id = rec.object_id;
+        Some(rec)
+    }
+
+    fn transmittance(&self, r: &Ray, ray_t: Interval, sampler: &mut Sampler) -> f32 {
+        self.triangles.transmittance(r, ray_t, sampler)
+    }
 }
 
 #[derive(Clone)]
@@ -91,6 +132,41 @@ impl Triangle {
 
         Triangle { v0, v1, v2, normal, mat: mat.unwrap_or_default(), bbox }
     }
+    pub(crate) fn material(&self) -> &Material { &self.mat }
+
+    pub(crate) fn area(&self) -> f32 {
+        (self.v1-self.v0).cross(&(self.v2-self.v0)).norm() / 2.
+    }
+
+    /// Uniformly distributed point on the triangle and the normal of its front
+    pub(crate) fn sample_point(&self, sampler: &mut Sampler) -> (Point3, Vec3) {
+        let (u1, u2) = sampler.next_2d();
+        let s = u1.sqrt();
+        ((1. - s)*self.v0 + s*(1. - u2)*self.v1 + s*u2*self.v2, self.normal)
+    }
+
+    /// Uniformly distributed point on the front of the triangle as seen from
+    /// `origin`: unit direction, distance and the solid angle pdf of the direction
+    pub(crate) fn sample_direction(&self, origin: Point3, sampler: &mut Sampler) -> Option<(Vec3, f32, f32)> {
+        let (point, _) = self.sample_point(sampler);
+        let to_point = point - origin;
+        let distance = to_point.norm();
+        let direction = to_point / distance;
+        // back faces are culled, so they cannot be seen
+        let cosine = -self.normal.dot(&direction);
+        if cosine < 1e-6 { return None }
+
+        Some((direction, distance, distance*distance / (cosine * self.area())))
+    }
+
+    /// Solid angle pdf of `sample_direction` for a ray that hits the triangle at `t`
+    pub(crate) fn direction_pdf(&self, r: &Ray, t: f32) -> f32 {
+        let distance = t * r.direction().norm();
+        let cosine = self.normal.dot(&r.direction().normalize()).abs();
+        distance*distance / (cosine * self.area())
+    }
+
+    #[allow(dead_code)]
     fn with_material(self, mat: Material) -> Self {
         let mut new = self;
         new.mat = mat;
@@ -113,7 +189,7 @@ impl Hittable for Triangle {
 
         let tvec = r.origin() - self.v0;
         let u = tvec.dot(&pvec) * inv_det;
-        if u < 0. || u > 1. { return None }
+        if !(0.0..=1.0).contains(&u) { return None }
 
         let qvec = tvec.cross(&v0v1);
         let v = r.direction().dot(&qvec) * inv_det;
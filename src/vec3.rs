@@ -1,4 +1,5 @@
 use nalgebra::Vector3;
+use rand::Rng;
 
 pub type Vec3 = Vector3<f32>;
 pub type Point3 = Vec3;
@@ -82,3 +83,8 @@ pub fn refract(vec: Vec3, n: &Vec3, etai_over_etat: f32) -> Vec3 {
     r_out_perp + r_out_parallel
 }
 
+
+/// Vector with all components uniformly distributed in [0, 1)
+pub fn random_vec<R: Rng + ?Sized>(rng: &mut R) -> Vec3 {
+    Vec3::new(rng.gen(), rng.gen(), rng.gen())
+}
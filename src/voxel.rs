@@ -0,0 +1,155 @@
+use std::{fs, path::Path};
+
+use anyhow::{Result, bail, ensure};
+
+use crate::{
+    aabb::AABB,
+    vec3::Point3,
+};
+
+/// Values on a regular 3D grid filling a box, such as the density or the
+/// temperature of a smoke simulation, interpolated in between
+#[derive(Clone)]
+pub struct VoxelGrid {
+    size: [usize; 3],
+    /// x changes fastest, then y, then z
+    values: Vec<f32>,
+    bbox: AABB,
+    max: f32,
+}
+
+impl VoxelGrid {
+    /// Grid of `size` voxels filling the box between the opposite corners
+    /// `a` and `b`
+    pub fn new(size: [usize; 3], values: Vec<f32>, a: Point3, b: Point3) -> Result<Self> {
+        ensure!(size.iter().all(|&n| n > 0), "empty voxel grid");
+        ensure!(values.len() == size.iter().product(),
+            "{} voxel values for a {}x{}x{} grid", values.len(), size[0], size[1], size[2]);
+        let max = values.iter().fold(0f32, |max, &value| max.max(value));
+        Ok(VoxelGrid { size, values, bbox: AABB::from_points(a, b), max })
+    }
+
+    /// Reads a Mitsuba .vol file with one channel of 32 bit floats or bytes,
+    /// the box it fills is in its header. Any other file is read as raw
+    /// little-endian 32 bit floats of a cubic grid filling the unit cube.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let bytes = fs::read(path.as_ref())?;
+        match bytes.strip_prefix(b"VOL") {
+            Some(vol) => Self::from_vol(vol),
+            None => Self::from_raw(&bytes),
+        }
+    }
+
+    /// Largest value in the grid, and so anywhere in between
+    pub fn max(&self) -> f32 {
+        self.max
+    }
+
+    pub(crate) fn bounding_box(&self) -> AABB {
+        self.bbox
+    }
+
+    /// Trilinear interpolation of the values at the voxel centers, zero
+    /// outside the grid's box
+    pub fn value(&self, p: Point3) -> f32 {
+        let mut lower = [0; 3];
+        let mut fraction = [0.; 3];
+        for axis in 0..3 {
+            let extent = self.bbox.axis(axis);
+            if !extent.contains(p[axis]) { return 0. }
+            let x = (p[axis] - extent.min) / extent.size() * self.size[axis] as f32 - 0.5;
+            let floor = x.floor().clamp(0., (self.size[axis] - 1) as f32);
+            lower[axis] = floor as usize;
+            fraction[axis] = (x - floor).clamp(0., 1.);
+        }
+
+        let mut value = 0.;
+        for corner in 0..8 {
+            let mut index = [0; 3];
+            let mut weight = 1.;
+            for axis in 0..3 {
+                let upper = corner >> axis & 1 == 1;
+                index[axis] = (lower[axis] + upper as usize).min(self.size[axis] - 1);
+                weight *= if upper { fraction[axis] } else { 1. - fraction[axis] };
+            }
+            value += weight * self.values[(index[2] * self.size[1] + index[1]) * self.size[0] + index[0]];
+        }
+        value
+    }
+
+    // private
+    fn from_vol(bytes: &[u8]) -> Result<Self> {
+        ensure!(bytes.len() >= 45, "truncated .vol header");
+        let int = |offset: usize| i32::from_le_bytes(bytes[offset..offset + 4].try_into().expect("4 bytes"));
+        let float = |offset: usize| f32::from_le_bytes(bytes[offset..offset + 4].try_into().expect("4 bytes"));
+        ensure!(bytes[0] == 3, ".vol version {} instead of 3", bytes[0]);
+        let (encoding, channels) = (int(1), int(17));
+        ensure!(channels == 1, ".vol files with {} channels are not supported", channels);
+
+        let size = [int(5), int(9), int(13)].map(|n| n.max(0) as usize);
+        let count: usize = size.iter().product();
+        let data = &bytes[45..];
+        let values = match encoding {
+            1 => {
+                ensure!(data.len() >= 4 * count, "truncated .vol data");
+                data.chunks_exact(4).take(count)
+                    .map(|value| f32::from_le_bytes(value.try_into().expect("4 bytes")))
+                    .collect()
+            },
+            3 => {
+                ensure!(data.len() >= count, "truncated .vol data");
+                data[..count].iter().map(|&value| value as f32 / 255.).collect()
+            },
+            _ => bail!(".vol encoding {} is not supported", encoding),
+        };
+
+        let min = Point3::new(float(21), float(25), float(29));
+        let max = Point3::new(float(33), float(37), float(41));
+        Self::new(size, values, min, max)
+    }
+
+    fn from_raw(bytes: &[u8]) -> Result<Self> {
+        ensure!(bytes.len().is_multiple_of(4), "raw voxel data is not made of 32 bit floats");
+        let count = bytes.len() / 4;
+        let n = (count as f64).cbrt().round() as usize;
+        ensure!(n * n * n == count, "{} raw voxel values do not make a cubic grid", count);
+
+        let values = bytes.chunks_exact(4)
+            .map(|value| f32::from_le_bytes(value.try_into().expect("4 bytes")))
+            .collect();
+        Self::new([n; 3], values, Point3::zeros(), Point3::repeat(1.))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::write;
+    use tempdir::TempDir;
+    use super::*;
+
+    #[test]
+    fn load_vol_and_raw() -> Result<()> {
+        let tmp_dir = TempDir::new("testing")?;
+        // 2x1x1 grid from 0 to 4 in x
+        let mut vol = b"VOL\x03".to_vec();
+        for int in [1, 2, 1, 1, 1] { vol.extend(i32::to_le_bytes(int)) }
+        for float in [0., -1., -1., 4., 1., 1., 1., 3.] { vol.extend(f32::to_le_bytes(float)) }
+        let path = tmp_dir.path().join("grid.vol");
+        write(&path, vol)?;
+
+        let grid = VoxelGrid::load(&path)?;
+        assert_eq!(grid.max(), 3.);
+        // voxel centers at x = 1 and 3, constant beyond them
+        assert_eq!(grid.value(Point3::new(0.5, 0., 0.)), 1.);
+        assert_eq!(grid.value(Point3::new(2., 0., 0.)), 2.);
+        assert_eq!(grid.value(Point3::new(2.5, 0.5, -0.5)), 2.5);
+        assert_eq!(grid.value(Point3::new(5., 0., 0.)), 0.);
+
+        let path = tmp_dir.path().join("grid.raw");
+        write(&path, [0.5f32; 8].map(f32::to_le_bytes).concat())?;
+        assert_eq!(VoxelGrid::load(&path)?.value(Point3::new(0.3, 0.6, 0.9)), 0.5);
+        write(&path, [0.5f32; 7].map(f32::to_le_bytes).concat())?;
+        assert!(VoxelGrid::load(&path).is_err());
+        Ok(())
+    }
+}